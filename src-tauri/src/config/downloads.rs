@@ -0,0 +1,907 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use futures::StreamExt;
+use lazy_static::lazy_static;
+use reqwest::header::{HeaderMap, HeaderName, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::{Client, StatusCode};
+use rusqlite::Connection;
+use serde::{Serialize, Deserialize};
+use tauri::api::path;
+use tauri::State;
+use tokio::sync::watch;
+use crate::db::db::Db;
+use crate::db::settings::{get_setting, set_setting};
+use crate::error::{AppError, ErrorCode};
+
+const MAX_CONCURRENT_KEY: &str = "max_concurrent_downloads";
+const DEFAULT_MAX_CONCURRENT: usize = 2;
+// Network errors in a row before a download is marked as failed
+const MAX_RETRIES: u32 = 5;
+// Multiplied by the number of the retry
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+// Data is written to "<filename>.part" and the server's validators to "<filename>.part.json"
+const PART_SUFFIX: &str = ".part";
+const PART_META_SUFFIX: &str = ".part.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DownloadKind {
+    LanguageModel,
+    EmbeddingModel,
+    // llama.cpp release archives, unpacked with unzip_setup
+    Setup,
+}
+
+const DOWNLOAD_KINDS: [DownloadKind; 3] = [DownloadKind::LanguageModel, DownloadKind::EmbeddingModel, DownloadKind::Setup];
+
+impl DownloadKind {
+    fn dir(&self) -> Result<PathBuf, AppError> {
+        let doc_dir = path::document_dir()
+            .ok_or_else(|| AppError::internal("Failed to get documents directory"))?;
+
+        let levchat_dir = doc_dir.join("LevChat");
+        Ok(match self {
+            DownloadKind::LanguageModel => levchat_dir.join("model"),
+            DownloadKind::EmbeddingModel => levchat_dir.join("em_model"),
+            DownloadKind::Setup => levchat_dir.join("setup"),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadStatus {
+    // Waiting for a free slot
+    Queued,
+    Downloading,
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl DownloadStatus {
+    fn is_finished(&self) -> bool {
+        matches!(self, DownloadStatus::Completed | DownloadStatus::Failed | DownloadStatus::Cancelled)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadInfo {
+    pub id: u64,
+    pub url: String,
+    pub filename: String,
+    pub kind: DownloadKind,
+    pub status: DownloadStatus,
+    // None until the server has answered
+    pub total_size: Option<u64>,
+    pub downloaded_size: u64,
+    pub percentage: Option<u8>,
+    // Why the download failed
+    pub error: Option<AppError>,
+}
+
+// What is known about the data in a .part file, so that a later request only asks for
+// the rest of it if the file on the server has not changed since.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PartialDownload {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    total_size: Option<u64>,
+}
+
+// What the user last asked a running download to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Control {
+    Run,
+    Pause,
+    Cancel,
+}
+
+// How a transfer ended when it did not fail
+enum Outcome {
+    Completed,
+    Paused,
+}
+
+struct Download {
+    info: DownloadInfo,
+    path: PathBuf,
+    // Whether a task is running for it
+    started: bool,
+    // Delete the .part file once the download is cancelled
+    discard: bool,
+    control: watch::Sender<Control>,
+}
+
+// Every download since the app started, and the unfinished ones found on disk at
+// startup. A download holds one of the `max_concurrent` slots while its task runs;
+// pausing closes the connection and frees the slot.
+struct DownloadManager {
+    next_id: u64,
+    max_concurrent: usize,
+    downloads: BTreeMap<u64, Download>,
+}
+
+lazy_static! {
+    static ref DOWNLOADS: Mutex<DownloadManager> = Mutex::new(DownloadManager {
+        next_id: 1,
+        max_concurrent: DEFAULT_MAX_CONCURRENT,
+        downloads: BTreeMap::new(),
+    });
+}
+
+fn manager() -> Result<MutexGuard<'static, DownloadManager>, AppError> {
+    DOWNLOADS.lock()
+        .map_err(|_| AppError::internal("Download manager is unavailable"))
+}
+
+impl DownloadManager {
+    fn get_mut(&mut self, id: u64) -> Result<&mut Download, AppError> {
+        self.downloads.get_mut(&id)
+            .ok_or_else(|| AppError::not_found(format!("Download {} not found", id)))
+    }
+
+    fn insert(&mut self, info: DownloadInfo, path: PathBuf) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let (control, _) = watch::channel(Control::Run);
+        let info = DownloadInfo { id, ..info };
+        self.downloads.insert(id, Download { info, path, started: false, discard: false, control });
+        id
+    }
+
+    // Starts queued downloads, oldest first, while slots are free.
+    fn schedule(&mut self) {
+        let active = self.downloads.values()
+            .filter(|download| download.started)
+            .count();
+
+        let ready: Vec<u64> = self.downloads.values()
+            .filter(|download| download.info.status == DownloadStatus::Queued && !download.started)
+            .map(|download| download.info.id)
+            .take(self.max_concurrent.saturating_sub(active))
+            .collect();
+
+        for id in ready {
+            if let Some(download) = self.downloads.get_mut(&id) {
+                download.started = true;
+                download.info.status = DownloadStatus::Downloading;
+                let url = download.info.url.clone();
+                let path = download.path.clone();
+                let control = download.control.subscribe();
+                tauri::async_runtime::spawn(run_download(id, url, path, control));
+            }
+        }
+    }
+}
+
+fn with_percentage(mut info: DownloadInfo) -> DownloadInfo {
+    info.percentage = info.total_size
+        .filter(|total| *total > 0)
+        .map(|total| (info.downloaded_size as f64 / total as f64 * 100.0).round() as u8);
+    info
+}
+
+fn update(id: u64, change: impl FnOnce(&mut DownloadInfo)) -> Result<(), AppError> {
+    let mut manager = manager()?;
+    change(&mut manager.get_mut(id)?.info);
+    Ok(())
+}
+
+fn downloaded_size(id: u64) -> u64 {
+    manager().ok()
+        .and_then(|manager| manager.downloads.get(&id).map(|download| download.info.downloaded_size))
+        .unwrap_or(0)
+}
+
+fn filename_from_url(url: &str) -> Result<String, AppError> {
+    url.split(['?', '#'])
+        .next()
+        .and_then(|url| url.rsplit('/').next())
+        .filter(|filename| !filename.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| AppError::invalid_input("Could not extract filename from URL"))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    with_suffix(path, PART_SUFFIX)
+}
+
+fn part_meta_path(path: &Path) -> PathBuf {
+    with_suffix(path, PART_META_SUFFIX)
+}
+
+// The partial download at `path` and the number of bytes it holds.
+fn read_partial(path: &Path) -> Option<(PartialDownload, u64)> {
+    let meta = fs::read_to_string(part_meta_path(path)).ok()?;
+    let meta: PartialDownload = serde_json::from_str(&meta).ok()?;
+    let size = fs::metadata(part_path(path)).ok()?.len();
+    Some((meta, size))
+}
+
+fn write_partial(path: &Path, meta: &PartialDownload) -> Result<(), AppError> {
+    let json = serde_json::to_string(meta)
+        .map_err(|e| AppError::with_cause("Failed to serialize download state", e))?;
+    fs::write(part_meta_path(path), json)
+        .map_err(|e| AppError::with_cause("Failed to save download state", e))
+}
+
+fn remove_partial(path: &Path) {
+    fs::remove_file(part_path(path)).ok();
+    fs::remove_file(part_meta_path(path)).ok();
+}
+
+fn header(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers.get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+// If-Range only accepts a strong ETag, otherwise the modification date is compared.
+fn if_range(meta: &PartialDownload) -> Option<&str> {
+    match meta.etag.as_deref() {
+        Some(etag) if !etag.starts_with("W/") => Some(etag),
+        _ => meta.last_modified.as_deref(),
+    }
+}
+
+// First byte and total length of a Content-Range such as "bytes 100-999/1000".
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+    Some((start.parse().ok()?, total.parse().ok()))
+}
+
+fn cancelled() -> AppError {
+    AppError::cancelled("Download cancelled")
+}
+
+// Continues the download from the end of its .part file until it is complete or
+// paused. Errors a retry may get past have ErrorCode::Network.
+async fn transfer(id: u64, url: &str, file_path: &Path, control: &mut watch::Receiver<Control>) -> Result<Outcome, AppError> {
+    let requested = *control.borrow_and_update();
+    match requested {
+        Control::Run => {}
+        Control::Pause => return Ok(Outcome::Paused),
+        Control::Cancel => return Err(cancelled()),
+    }
+
+    // Data for another URL under the same name can't be continued
+    let (mut meta, mut offset) = match read_partial(file_path) {
+        Some((meta, size)) if meta.url == url => (meta, size),
+        _ => {
+            remove_partial(file_path);
+            (PartialDownload { url: url.to_string(), ..Default::default() }, 0)
+        }
+    };
+
+    let mut request = Client::new().get(url);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+        if let Some(validator) = if_range(&meta) {
+            request = request.header(IF_RANGE, validator);
+        }
+    }
+
+    let response = request.send()
+        .await
+        .map_err(|e| AppError::with_cause("Failed to initiate download", e))?;
+
+    let status = response.status();
+    if offset > 0 && status == StatusCode::RANGE_NOT_SATISFIABLE && meta.total_size == Some(offset) {
+        // Everything had arrived before the app stopped, only the rename is missing
+        return finish(file_path);
+    }
+    if status == StatusCode::RANGE_NOT_SATISFIABLE {
+        remove_partial(file_path);
+        return Err(AppError::new(ErrorCode::Network, "Server rejected the resume position, starting over"));
+    }
+    if status.is_server_error() {
+        return Err(AppError::new(ErrorCode::Network, format!("Server returned status: {}", status)));
+    }
+    if !status.is_success() {
+        return Err(AppError::invalid_input(format!("Invalid download link. Server returned status: {}", status)));
+    }
+
+    if offset > 0 && status == StatusCode::PARTIAL_CONTENT {
+        let range = header(response.headers(), CONTENT_RANGE)
+            .and_then(|value| parse_content_range(&value));
+
+        match range {
+            Some((start, total)) if start == offset => meta.total_size = total.or(meta.total_size),
+            _ => {
+                remove_partial(file_path);
+                return Err(AppError::new(ErrorCode::Network, "Server resumed at the wrong position, starting over"));
+            }
+        }
+    } else {
+        // The whole file, because it changed on the server or the server ignores ranges
+        offset = 0;
+        meta.etag = header(response.headers(), ETAG);
+        meta.last_modified = header(response.headers(), LAST_MODIFIED);
+        meta.total_size = response.content_length();
+    }
+
+    // Without a length an early end of the connection can't be told from the end of the file
+    let total_size = meta.total_size.ok_or_else(|| {
+        AppError::invalid_input("Server did not send the size of the file, so the download can't be checked")
+    })?;
+
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| AppError::with_cause("Failed to create directory", e))?;
+    }
+    write_partial(file_path, &meta)?;
+
+    update(id, |info| {
+        info.total_size = Some(total_size);
+        info.downloaded_size = offset;
+    })?;
+
+    let mut file = if offset > 0 {
+        OpenOptions::new().append(true).open(part_path(file_path))
+    } else {
+        File::create(part_path(file_path))
+    }.map_err(|e| AppError::with_cause("Failed to create file", e))?;
+
+    let mut stream = response.bytes_stream();
+    let mut downloaded_size = offset;
+
+    loop {
+        tokio::select! {
+            item = stream.next() => {
+                let chunk = match item {
+                    Some(chunk) => chunk.map_err(|e| AppError::with_cause("Error downloading chunk", e))?,
+                    None => break,
+                };
+
+                file.write_all(&chunk)
+                    .map_err(|e| AppError::with_cause("Failed to write chunk", e))?;
+
+                downloaded_size += chunk.len() as u64;
+                update(id, |info| info.downloaded_size = downloaded_size)?;
+            }
+            changed = control.changed() => {
+                if changed.is_err() {
+                    return Err(cancelled());
+                }
+
+                let requested = *control.borrow_and_update();
+                match requested {
+                    Control::Run => {}
+                    Control::Pause => return Ok(Outcome::Paused),
+                    Control::Cancel => return Err(cancelled()),
+                }
+            }
+        }
+    }
+
+    file.flush()
+        .map_err(|e| AppError::with_cause("Failed to write file", e))?;
+
+    if downloaded_size != total_size {
+        return Err(AppError::new(ErrorCode::Network, format!(
+            "Download ended after {} of {} bytes", downloaded_size, total_size
+        )));
+    }
+
+    finish(file_path)
+}
+
+// Moves a complete .part file to its final name.
+fn finish(file_path: &Path) -> Result<Outcome, AppError> {
+    fs::rename(part_path(file_path), file_path)
+        .map_err(|e| AppError::with_cause("Failed to move downloaded file into place", e))?;
+    fs::remove_file(part_meta_path(file_path)).ok();
+    Ok(Outcome::Completed)
+}
+
+async fn run_download(id: u64, url: String, file_path: PathBuf, mut control: watch::Receiver<Control>) {
+    let mut retries = 0;
+
+    let result = loop {
+        let before = downloaded_size(id);
+
+        match transfer(id, &url, &file_path, &mut control).await {
+            Err(e) if e.code == ErrorCode::Network && retries < MAX_RETRIES => {
+                // Only failures without progress in between count towards the limit
+                if downloaded_size(id) > before {
+                    retries = 0;
+                }
+                retries += 1;
+                log::warn!("Download of {} interrupted, retrying: {}", url, e);
+
+                // A pause or cancel cuts the wait short and is handled by the next transfer
+                tokio::select! {
+                    _ = tokio::time::sleep(RETRY_DELAY * retries) => {}
+                    changed = control.changed() => {
+                        if changed.is_err() {
+                            break Err(cancelled());
+                        }
+                    }
+                }
+            }
+            result => break result,
+        }
+    };
+
+    let mut manager = match manager() {
+        Ok(manager) => manager,
+        Err(_) => return,
+    };
+
+    if let Ok(download) = manager.get_mut(id) {
+        download.started = false;
+        let resumed = *download.control.borrow() == Control::Run;
+
+        match result {
+            Ok(Outcome::Completed) => {
+                download.info.status = DownloadStatus::Completed;
+                log::info!("Downloaded {}", download.info.filename);
+            }
+            // Resumed again before the task had stopped
+            Ok(Outcome::Paused) if resumed => download.info.status = DownloadStatus::Queued,
+            Err(e) if e.code == ErrorCode::Cancelled && resumed => download.info.status = DownloadStatus::Queued,
+            Ok(Outcome::Paused) => download.info.status = DownloadStatus::Paused,
+            Err(e) if e.code == ErrorCode::Cancelled => {
+                download.info.status = DownloadStatus::Cancelled;
+                if download.discard {
+                    remove_partial(&download.path);
+                }
+            }
+            // The .part file stays, so resume_download continues where it stopped
+            Err(e) => {
+                log::warn!("Download of {} failed: {}", download.info.filename, e);
+                download.info.status = DownloadStatus::Failed;
+                download.info.error = Some(e);
+            }
+        }
+    }
+
+    manager.schedule();
+}
+
+// Queues a download into the folder for `kind`. It starts as soon as a slot is free,
+// continuing a partial download of the same URL if there is one.
+pub fn start_download(url: String, kind: DownloadKind) -> Result<DownloadInfo, AppError> {
+    let filename = filename_from_url(&url)?;
+    let path = kind.dir()?.join(&filename);
+
+    if path.exists() {
+        return Err(AppError::already_exists(format!("{} already exists", filename)));
+    }
+
+    let mut manager = manager()?;
+
+    let in_progress = manager.downloads.values()
+        .any(|download| download.path == path && (download.started || !download.info.status.is_finished()));
+    if in_progress {
+        return Err(AppError::busy(format!("{} is already being downloaded", filename)));
+    }
+
+    // A failed or cancelled download of the file is continued by this one
+    manager.downloads.retain(|_, download| download.path != path);
+
+    let partial = read_partial(&path).filter(|(meta, _)| meta.url == url);
+
+    let id = manager.insert(DownloadInfo {
+        id: 0,
+        url,
+        filename,
+        kind,
+        status: DownloadStatus::Queued,
+        total_size: partial.as_ref().and_then(|(meta, _)| meta.total_size),
+        downloaded_size: partial.map(|(_, size)| size).unwrap_or(0),
+        percentage: None,
+        error: None,
+    }, path);
+    manager.schedule();
+
+    get_download_in(&manager, id)
+}
+
+// Lists the unfinished downloads of earlier runs as paused, so they can be resumed
+// or cancelled. Called once from setup.
+pub fn restore_partial_downloads() -> Result<usize, AppError> {
+    let mut manager = manager()?;
+    let mut restored = 0;
+
+    for kind in DOWNLOAD_KINDS {
+        let dir = kind.dir()?;
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let filename = match name.strip_suffix(PART_META_SUFFIX) {
+                Some(filename) => filename.to_string(),
+                None => continue,
+            };
+
+            let path = dir.join(&filename);
+            if path.exists() || manager.downloads.values().any(|download| download.path == path) {
+                continue;
+            }
+
+            if let Some((meta, size)) = read_partial(&path) {
+                manager.insert(DownloadInfo {
+                    id: 0,
+                    url: meta.url,
+                    filename,
+                    kind,
+                    status: DownloadStatus::Paused,
+                    total_size: meta.total_size,
+                    downloaded_size: size,
+                    percentage: None,
+                    error: None,
+                }, path);
+                restored += 1;
+            }
+        }
+    }
+
+    Ok(restored)
+}
+
+fn get_download_in(manager: &DownloadManager, id: u64) -> Result<DownloadInfo, AppError> {
+    manager.downloads.get(&id)
+        .map(|download| with_percentage(download.info.clone()))
+        .ok_or_else(|| AppError::not_found(format!("Download {} not found", id)))
+}
+
+pub fn get_download(id: u64) -> Result<DownloadInfo, AppError> {
+    get_download_in(&*manager()?, id)
+}
+
+// Every download since the app started, oldest first.
+pub fn list_downloads() -> Result<Vec<DownloadInfo>, AppError> {
+    Ok(manager()?.downloads.values()
+        .map(|download| with_percentage(download.info.clone()))
+        .collect())
+}
+
+pub fn pause_download(id: u64) -> Result<(), AppError> {
+    let mut manager = manager()?;
+    let download = manager.get_mut(id)?;
+
+    match download.info.status {
+        DownloadStatus::Paused => Ok(()),
+        DownloadStatus::Queued | DownloadStatus::Downloading => {
+            download.info.status = DownloadStatus::Paused;
+            download.control.send_replace(Control::Pause);
+            Ok(())
+        }
+        _ => Err(AppError::invalid_input(format!("Download {} has already finished", id))),
+    }
+}
+
+// Continues a paused, failed or cancelled download from its .part file.
+pub fn resume_download(id: u64) -> Result<(), AppError> {
+    let mut manager = manager()?;
+    let download = manager.get_mut(id)?;
+
+    match download.info.status {
+        DownloadStatus::Queued | DownloadStatus::Downloading => return Ok(()),
+        DownloadStatus::Completed => {
+            return Err(AppError::invalid_input(format!("Download {} has already finished", id)));
+        }
+        DownloadStatus::Paused | DownloadStatus::Failed | DownloadStatus::Cancelled => {}
+    }
+
+    if download.path.exists() {
+        return Err(AppError::already_exists(format!("{} already exists", download.info.filename)));
+    }
+
+    download.control.send_replace(Control::Run);
+    download.discard = false;
+    download.info.error = None;
+    download.info.status = if download.started {
+        DownloadStatus::Downloading
+    } else {
+        DownloadStatus::Queued
+    };
+
+    manager.schedule();
+    Ok(())
+}
+
+// Stops a download. Its .part file is kept for resume_download unless `discard` is set.
+pub fn cancel_download(id: u64, discard: bool) -> Result<(), AppError> {
+    let mut manager = manager()?;
+    let download = manager.get_mut(id)?;
+
+    if download.info.status == DownloadStatus::Completed {
+        return Err(AppError::invalid_input(format!("Download {} has already finished", id)));
+    }
+
+    download.info.status = DownloadStatus::Cancelled;
+    download.discard = discard;
+
+    if download.started {
+        download.control.send_replace(Control::Cancel);
+    } else if discard {
+        remove_partial(&download.path);
+    }
+
+    Ok(())
+}
+
+// Forgets completed, failed and cancelled downloads. Kept .part files are listed
+// again on the next start.
+pub fn clear_finished_downloads() -> Result<usize, AppError> {
+    let mut manager = manager()?;
+    let before = manager.downloads.len();
+    manager.downloads.retain(|_, download| download.started || !download.info.status.is_finished());
+    Ok(before - manager.downloads.len())
+}
+
+pub fn get_max_concurrent_downloads() -> Result<usize, AppError> {
+    Ok(manager()?.max_concurrent)
+}
+
+pub fn set_max_concurrent_downloads(conn: &Connection, max_concurrent: usize) -> Result<(), AppError> {
+    if max_concurrent == 0 {
+        return Err(AppError::invalid_input("At least one download must be allowed at a time"));
+    }
+
+    set_setting(conn, MAX_CONCURRENT_KEY, &max_concurrent)?;
+
+    let mut manager = manager()?;
+    manager.max_concurrent = max_concurrent;
+    manager.schedule();
+    Ok(())
+}
+
+// Applies the saved maximum concurrency. Called once from setup.
+pub fn load_download_settings(db: &Db) -> Result<(), AppError> {
+    // Stays at the default until an encrypted database is unlocked
+    if db.is_locked()? {
+        return Ok(());
+    }
+
+    if let Some(max_concurrent) = get_setting::<usize>(&*db.conn()?, MAX_CONCURRENT_KEY)? {
+        manager()?.max_concurrent = max_concurrent.max(1);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn start_download_command(url: String, kind: DownloadKind) -> Result<DownloadInfo, AppError> {
+    start_download(url, kind)
+}
+
+#[tauri::command]
+pub fn get_download_command(id: u64) -> Result<DownloadInfo, AppError> {
+    get_download(id)
+}
+
+#[tauri::command]
+pub fn list_downloads_command() -> Result<Vec<DownloadInfo>, AppError> {
+    list_downloads()
+}
+
+#[tauri::command]
+pub fn pause_download_command(id: u64) -> Result<(), AppError> {
+    pause_download(id)
+}
+
+#[tauri::command]
+pub fn resume_download_command(id: u64) -> Result<(), AppError> {
+    resume_download(id)
+}
+
+#[tauri::command]
+pub fn cancel_download_command(id: u64, discard: Option<bool>) -> Result<(), AppError> {
+    cancel_download(id, discard.unwrap_or(false))
+}
+
+#[tauri::command]
+pub fn clear_finished_downloads_command() -> Result<usize, AppError> {
+    clear_finished_downloads()
+}
+
+#[tauri::command]
+pub fn get_max_concurrent_downloads_command() -> Result<usize, AppError> {
+    get_max_concurrent_downloads()
+}
+
+#[tauri::command]
+pub fn set_max_concurrent_downloads_command(db: State<'_, Db>, max_concurrent: usize) -> Result<(), AppError> {
+    set_max_concurrent_downloads(&*db.conn()?, max_concurrent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const ETAG_VALUE: &str = "\"v1\"";
+
+    // Range and If-Range headers of every request
+    type RequestLog = Arc<Mutex<Vec<(Option<String>, Option<String>)>>>;
+
+    // Serves one file over HTTP/1.1 with Range and If-Range support. The first
+    // `drops` responses are cut off after `drop_after` bytes of body.
+    struct TestServer {
+        url: String,
+        requests: RequestLog,
+    }
+
+    fn test_data() -> Vec<u8> {
+        (0..256 * 1024).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn request_header(request: &str, name: &str) -> Option<String> {
+        request.lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim().to_string())
+    }
+
+    async fn serve(data: Vec<u8>, drops: usize, drop_after: usize, send_length: bool) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/model.gguf", listener.local_addr().unwrap());
+        let requests: RequestLog = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+
+        tokio::spawn(async move {
+            let mut served = 0;
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buffer = Vec::new();
+                let mut chunk = [0u8; 1024];
+                while !buffer.windows(4).any(|window| window == b"\r\n\r\n") {
+                    let read = socket.read(&mut chunk).await.unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    buffer.extend_from_slice(&chunk[..read]);
+                }
+
+                let request = String::from_utf8_lossy(&buffer).to_string();
+                let range = request_header(&request, "range");
+                let if_range = request_header(&request, "if-range");
+                log.lock().unwrap().push((range.clone(), if_range.clone()));
+
+                let unchanged = if_range.is_none() || if_range.as_deref() == Some(ETAG_VALUE);
+                let start = match &range {
+                    Some(range) if unchanged => range.trim_start_matches("bytes=").trim_end_matches('-').parse::<usize>().unwrap(),
+                    _ => 0,
+                };
+
+                let body = &data[start..];
+                let mut head = if start > 0 {
+                    format!("HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n", start, data.len() - 1, data.len())
+                } else {
+                    "HTTP/1.1 200 OK\r\n".to_string()
+                };
+                if send_length {
+                    head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+                }
+                head.push_str(&format!("ETag: {}\r\nConnection: close\r\n\r\n", ETAG_VALUE));
+
+                socket.write_all(head.as_bytes()).await.unwrap();
+                let cut = served < drops;
+                served += 1;
+                let end = if cut { drop_after.min(body.len()) } else { body.len() };
+                let _ = socket.write_all(&body[..end]).await;
+                let _ = socket.flush().await;
+                if cut {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                // Dropping the socket closes the connection, mid-body when `cut`
+            }
+        });
+
+        TestServer { url, requests }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("levchat-downloads-{}-{}", std::process::id(), name));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("model.gguf")
+    }
+
+    fn register(url: &str, path: &Path) -> u64 {
+        manager().unwrap().insert(DownloadInfo {
+            id: 0,
+            url: url.to_string(),
+            filename: "model.gguf".to_string(),
+            kind: DownloadKind::LanguageModel,
+            status: DownloadStatus::Downloading,
+            total_size: None,
+            downloaded_size: 0,
+            percentage: None,
+            error: None,
+        }, path.to_path_buf())
+    }
+
+    async fn run(id: u64, url: &str, path: &Path) -> Result<Outcome, AppError> {
+        let (_control, mut receiver) = watch::channel(Control::Run);
+        transfer(id, url, path, &mut receiver).await
+    }
+
+    #[tokio::test]
+    async fn resumes_after_dropped_connections() {
+        let data = test_data();
+        let server = serve(data.clone(), 2, 64 * 1024, true).await;
+        let path = temp_path("dropped");
+        let id = register(&server.url, &path);
+
+        let error = run(id, &server.url, &path).await.err().unwrap();
+        assert_eq!(error.code, ErrorCode::Network);
+        assert!(!path.exists());
+        let first_part = fs::metadata(part_path(&path)).unwrap().len();
+        assert!(first_part > 0 && first_part < data.len() as u64);
+
+        assert!(run(id, &server.url, &path).await.is_err());
+        let second_part = fs::metadata(part_path(&path)).unwrap().len();
+        assert!(second_part > first_part);
+
+        assert!(matches!(run(id, &server.url, &path).await, Ok(Outcome::Completed)));
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert!(!part_path(&path).exists());
+        assert!(!part_meta_path(&path).exists());
+
+        let requests = server.requests.lock().unwrap().clone();
+        assert_eq!(requests[0], (None, None));
+        assert_eq!(requests[1], (Some(format!("bytes={}-", first_part)), Some(ETAG_VALUE.to_string())));
+        assert_eq!(requests[2], (Some(format!("bytes={}-", second_part)), Some(ETAG_VALUE.to_string())));
+        assert_eq!(get_download(id).unwrap().downloaded_size, data.len() as u64);
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[tokio::test]
+    async fn restarts_when_the_file_changed() {
+        let data = test_data();
+        let server = serve(data.clone(), 0, 0, true).await;
+        let path = temp_path("changed");
+        let id = register(&server.url, &path);
+
+        // Left by an earlier run against an older version of the file
+        fs::write(part_path(&path), vec![0u8; 1000]).unwrap();
+        write_partial(&path, &PartialDownload {
+            url: server.url.clone(),
+            etag: Some("\"v0\"".to_string()),
+            last_modified: None,
+            total_size: Some(data.len() as u64),
+        }).unwrap();
+
+        assert!(matches!(run(id, &server.url, &path).await, Ok(Outcome::Completed)));
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        let requests = server.requests.lock().unwrap().clone();
+        assert_eq!(requests, vec![(Some("bytes=1000-".to_string()), Some("\"v0\"".to_string()))]);
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[tokio::test]
+    async fn never_renames_a_file_of_unknown_length() {
+        let server = serve(test_data(), 1, 64 * 1024, false).await;
+        let path = temp_path("unknown-length");
+        let id = register(&server.url, &path);
+
+        let error = run(id, &server.url, &path).await.err().unwrap();
+        assert_eq!(error.code, ErrorCode::InvalidInput);
+        assert!(!path.exists());
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}
@@ -0,0 +1,99 @@
+use lazy_static::lazy_static;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Mutex, PoisonError, TryLockError};
+use tauri::api::path;
+use crate::error::{AppError, ErrorCode};
+
+// Context size of background completions. Their prompts are short, and a small context
+// keeps the memory they need next to the loaded chat model low.
+const BACKGROUND_CONTEXT_TOKENS: u32 = 2048;
+
+lazy_static! {
+    // Held while llama-cli runs, so background tasks load a model one at a time
+    static ref COMPLETION_LOCK: Mutex<()> = Mutex::new(());
+}
+
+fn setup_dir() -> Result<PathBuf, AppError> {
+    let doc_dir = path::document_dir()
+        .ok_or_else(|| AppError::internal("Failed to get documents directory"))?;
+    Ok(doc_dir.join("LevChat").join("setup"))
+}
+
+pub fn model_path(model: &str) -> Result<PathBuf, AppError> {
+    let doc_dir = path::document_dir()
+        .ok_or_else(|| AppError::internal("Failed to get documents directory"))?;
+    let model_path = doc_dir.join("LevChat").join("model").join(model);
+
+    if !model_path.exists() {
+        return Err(AppError::not_found(format!("Model {} not found", model)));
+    }
+
+    Ok(model_path)
+}
+
+fn llama_cli_binary() -> Result<PathBuf, AppError> {
+    let setup_dir = setup_dir()?;
+
+    let llama_cli_path = setup_dir.join("llama-cli");
+    let llama_cli_exe_path = setup_dir.join("llama-cli.exe");
+
+    if llama_cli_path.exists() {
+        Ok(llama_cli_path)
+    } else if llama_cli_exe_path.exists() {
+        Ok(llama_cli_exe_path)
+    } else {
+        // Fallback to system-wide install (e.g. brew on macOS)
+        Ok(PathBuf::from("llama-cli"))
+    }
+}
+
+// Runs a single, non-interactive completion with llama-cli.
+// Used for short background tasks that should not go through the chat session.
+// Waits while another completion is running.
+pub fn complete(model: &str, prompt: &str, n_predict: u32) -> Result<String, AppError> {
+    let _guard = COMPLETION_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    run(model, prompt, n_predict)
+}
+
+// Like `complete`, but gives None instead of waiting when another completion is running.
+// For tasks that can be skipped, so they don't load the model a second time.
+pub fn complete_if_idle(model: &str, prompt: &str, n_predict: u32) -> Result<Option<String>, AppError> {
+    let _guard = match COMPLETION_LOCK.try_lock() {
+        Ok(guard) => guard,
+        Err(TryLockError::WouldBlock) => return Ok(None),
+        Err(TryLockError::Poisoned(e)) => e.into_inner(),
+    };
+    run(model, prompt, n_predict).map(Some)
+}
+
+fn run(model: &str, prompt: &str, n_predict: u32) -> Result<String, AppError> {
+    let model_path = model_path(model)?;
+    let binary = llama_cli_binary()?;
+
+    let output = Command::new(binary)
+        .arg("-m")
+        .arg(&model_path)
+        .arg("-p")
+        .arg(prompt)
+        .arg("-n")
+        .arg(n_predict.to_string())
+        .arg("-c")
+        .arg(BACKGROUND_CONTEXT_TOKENS.to_string())
+        .arg("--temp")
+        .arg("0")
+        .arg("--no-display-prompt")
+        .arg("--log-disable")
+        .output()
+        .map_err(|e| AppError::with_cause("Failed to run llama-cli", e))?;
+
+    if !output.status.success() {
+        return Err(AppError::new(ErrorCode::Model, format!(
+            "llama-cli exited with status {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -1,2 +1,4 @@
 pub mod config;
-pub mod setup;
\ No newline at end of file
+pub mod setup;
+pub mod llama_cli;
+pub mod downloads;
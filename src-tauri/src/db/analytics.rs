@@ -0,0 +1,277 @@
+use rusqlite::{Connection, params};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use tauri::State;
+use crate::db::db::Db;
+use crate::error::AppError;
+
+const CSV_HEADER: &str = "day,role,model,messages,prompt_tokens,completion_tokens,avg_latency_ms,avg_time_to_first_token_ms,rag_messages";
+
+// Limits the messages counted. Dates are YYYY-MM-DD and both ends are included.
+// With a model set only the replies of that model are counted, since user messages
+// have no model.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageFilter {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DailyUsage {
+    pub day: String,
+    pub user_messages: i64,
+    pub assistant_messages: i64,
+    pub other_messages: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModelUsage {
+    // None for replies saved without a model
+    pub model: Option<String>,
+    pub messages: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub avg_latency_ms: Option<f64>,
+    pub avg_time_to_first_token_ms: Option<f64>,
+    pub rag_messages: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UsageReport {
+    pub filter: UsageFilter,
+    pub total_messages: i64,
+    pub messages_per_day: Vec<DailyUsage>,
+    pub models: Vec<ModelUsage>,
+    // Averages over the assistant replies that recorded timings
+    pub avg_latency_ms: Option<f64>,
+    pub avg_time_to_first_token_ms: Option<f64>,
+    // Share of assistant replies that were given retrieved chunks, None without replies
+    pub rag_ratio: Option<f64>,
+}
+
+// Messages of one day, role and model. Sums and counts are kept apart so that
+// averages can be combined across rows.
+struct UsageRow {
+    day: String,
+    role: String,
+    model: Option<String>,
+    messages: i64,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    latency_sum: i64,
+    latency_count: i64,
+    first_token_sum: i64,
+    first_token_count: i64,
+    rag_messages: i64,
+}
+
+#[derive(Default)]
+struct Totals {
+    messages: i64,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    latency_sum: i64,
+    latency_count: i64,
+    first_token_sum: i64,
+    first_token_count: i64,
+    rag_messages: i64,
+}
+
+impl Totals {
+    fn add(&mut self, row: &UsageRow) {
+        self.messages += row.messages;
+        self.prompt_tokens += row.prompt_tokens;
+        self.completion_tokens += row.completion_tokens;
+        self.latency_sum += row.latency_sum;
+        self.latency_count += row.latency_count;
+        self.first_token_sum += row.first_token_sum;
+        self.first_token_count += row.first_token_count;
+        self.rag_messages += row.rag_messages;
+    }
+}
+
+fn average(sum: i64, count: i64) -> Option<f64> {
+    if count == 0 {
+        return None;
+    }
+    Some(sum as f64 / count as f64)
+}
+
+fn check_date(conn: &Connection, date: &Option<String>) -> Result<(), AppError> {
+    let date = match date {
+        Some(date) => date,
+        None => return Ok(()),
+    };
+
+    let parsed: Option<String> = conn.query_row("SELECT date(?1)", [date], |row| row.get(0))
+        .map_err(|e| AppError::with_cause("Failed to check date", e))?;
+
+    if parsed.as_deref() != Some(date.as_str()) {
+        return Err(AppError::invalid_input(format!("Invalid date {}, expected YYYY-MM-DD", date)));
+    }
+    Ok(())
+}
+
+fn usage_rows(conn: &Connection, filter: &UsageFilter) -> Result<Vec<UsageRow>, AppError> {
+    check_date(conn, &filter.from)?;
+    check_date(conn, &filter.to)?;
+
+    // Messages of chats in the trash are left out, as everywhere else
+    let mut stmt = conn.prepare_cached(
+        "SELECT date(m.timestamp) AS day,
+                COALESCE(m.role, CASE WHEN m.is_user THEN 'user' ELSE 'assistant' END) AS role,
+                m.model,
+                COUNT(*),
+                COALESCE(SUM(m.prompt_tokens), 0),
+                COALESCE(SUM(m.completion_tokens), 0),
+                COALESCE(SUM(m.latency_ms), 0),
+                COUNT(m.latency_ms),
+                COALESCE(SUM(m.time_to_first_token_ms), 0),
+                COUNT(m.time_to_first_token_ms),
+                SUM(EXISTS (SELECT 1 FROM message_sources s WHERE s.message_id = m.id))
+         FROM messages m
+         JOIN chats c ON c.id = m.chat_id
+         WHERE c.deleted_at IS NULL
+           AND (?1 IS NULL OR date(m.timestamp) >= ?1)
+           AND (?2 IS NULL OR date(m.timestamp) <= ?2)
+           AND (?3 IS NULL OR m.model = ?3)
+         GROUP BY day, role, m.model
+         ORDER BY day, role, m.model"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let rows = stmt.query_map(params![filter.from, filter.to, filter.model], |row| {
+        Ok(UsageRow {
+            day: row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+            role: row.get(1)?,
+            model: row.get(2)?,
+            messages: row.get(3)?,
+            prompt_tokens: row.get(4)?,
+            completion_tokens: row.get(5)?,
+            latency_sum: row.get(6)?,
+            latency_count: row.get(7)?,
+            first_token_sum: row.get(8)?,
+            first_token_count: row.get(9)?,
+            rag_messages: row.get(10)?,
+        })
+    }).map_err(|e| AppError::with_cause("Failed to query usage", e))?;
+
+    rows.collect::<Result<Vec<UsageRow>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect usage", e))
+}
+
+pub fn get_usage_report(conn: &Connection, filter: UsageFilter) -> Result<UsageReport, AppError> {
+    let rows = usage_rows(conn, &filter)?;
+
+    let mut days: BTreeMap<&str, DailyUsage> = BTreeMap::new();
+    let mut models: BTreeMap<Option<&str>, Totals> = BTreeMap::new();
+    let mut replies = Totals::default();
+    let mut total_messages = 0;
+
+    for row in &rows {
+        total_messages += row.messages;
+
+        let day = days.entry(&row.day).or_insert_with(|| DailyUsage {
+            day: row.day.clone(),
+            user_messages: 0,
+            assistant_messages: 0,
+            other_messages: 0,
+        });
+
+        match row.role.as_str() {
+            "user" => day.user_messages += row.messages,
+            "assistant" => {
+                day.assistant_messages += row.messages;
+                replies.add(row);
+                models.entry(row.model.as_deref()).or_default().add(row);
+            }
+            _ => day.other_messages += row.messages,
+        }
+    }
+
+    let mut models: Vec<ModelUsage> = models.into_iter()
+        .map(|(model, totals)| ModelUsage {
+            model: model.map(str::to_string),
+            messages: totals.messages,
+            prompt_tokens: totals.prompt_tokens,
+            completion_tokens: totals.completion_tokens,
+            avg_latency_ms: average(totals.latency_sum, totals.latency_count),
+            avg_time_to_first_token_ms: average(totals.first_token_sum, totals.first_token_count),
+            rag_messages: totals.rag_messages,
+        })
+        .collect();
+    models.sort_by_key(|model| std::cmp::Reverse(model.completion_tokens));
+
+    Ok(UsageReport {
+        total_messages,
+        messages_per_day: days.into_values().collect(),
+        models,
+        avg_latency_ms: average(replies.latency_sum, replies.latency_count),
+        avg_time_to_first_token_ms: average(replies.first_token_sum, replies.first_token_count),
+        rag_ratio: average(replies.rag_messages, replies.messages),
+        filter,
+    })
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_number(value: Option<f64>) -> String {
+    value.map(|value| format!("{:.1}", value)).unwrap_or_default()
+}
+
+// One line per day, role and model, so any other breakdown can be made from the file.
+pub fn render_usage_csv(conn: &Connection, filter: &UsageFilter) -> Result<String, AppError> {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+
+    for row in usage_rows(conn, filter)? {
+        let fields = [
+            csv_field(&row.day),
+            csv_field(&row.role),
+            csv_field(row.model.as_deref().unwrap_or("")),
+            row.messages.to_string(),
+            row.prompt_tokens.to_string(),
+            row.completion_tokens.to_string(),
+            csv_number(average(row.latency_sum, row.latency_count)),
+            csv_number(average(row.first_token_sum, row.first_token_count)),
+            row.rag_messages.to_string(),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+
+    Ok(csv)
+}
+
+pub fn export_usage_csv(conn: &Connection, filter: &UsageFilter, path: &str) -> Result<String, AppError> {
+    let csv = render_usage_csv(conn, filter)?;
+
+    let path = PathBuf::from(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| AppError::with_cause("Failed to create directory", e))?;
+    }
+
+    std::fs::write(&path, csv)
+        .map_err(|e| AppError::with_cause("Failed to write usage file", e))?;
+
+    Ok(path.display().to_string())
+}
+
+#[tauri::command]
+pub fn get_usage_report_command(db: State<'_, Db>, filter: Option<UsageFilter>) -> Result<UsageReport, AppError> {
+    get_usage_report(&*db.conn()?, filter.unwrap_or_default())
+}
+
+#[tauri::command]
+pub fn export_usage_csv_command(db: State<'_, Db>, filter: Option<UsageFilter>, path: String) -> Result<String, AppError> {
+    export_usage_csv(&*db.conn()?, &filter.unwrap_or_default(), &path)
+}
@@ -0,0 +1,510 @@
+use lopdf::Document as PdfDocument;
+use rusqlite::{Connection, Row, params};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager, State};
+use crate::db::chat_settings::get_chat_settings;
+use crate::db::db::{check_chat_writable, current_timestamp, get_db_path, get_message, Db, Message};
+use crate::rag::grounding::RetrievedChunk;
+use crate::rag::injection::prepare_sources;
+use crate::error::AppError;
+
+// Attachments are stored once per content in <workspace>/attachments/<first two hex
+// digits>/<sha256>, however many messages or chats refer to them. Their text is kept
+// in attachment_chunks so it can be pasted into a prompt or searched without reading
+// the file again.
+pub(crate) const ATTACHMENTS_DIR: &str = "attachments";
+const MAX_ATTACHMENT_BYTES: u64 = 50 * 1024 * 1024;
+// Longer text is indexed for retrieval unless the caller asks for inline
+const MAX_INLINE_CHARS: usize = 24_000;
+// Attachment text is split at line breaks into chunks of at most this many characters
+const CHUNK_CHARS: usize = 1_500;
+const DEFAULT_ATTACHMENT_SEARCH_LIMIT: u32 = 5;
+
+const ATTACHMENT_SELECT: &str =
+    "SELECT id, chat_id, message_id, filename, sha256, size, mode, created_at FROM attachments";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttachmentMode {
+    // The whole text goes into the prompt of the turn it was sent with
+    Inline,
+    // Chunks are retrieved for any later turn of the same chat
+    Indexed,
+}
+
+impl AttachmentMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AttachmentMode::Inline => "inline",
+            AttachmentMode::Indexed => "indexed",
+        }
+    }
+
+    fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "inline" => Some(AttachmentMode::Inline),
+            "indexed" => Some(AttachmentMode::Indexed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: i64,
+    pub chat_id: i64,
+    // None until it is linked to the message it was sent with
+    pub message_id: Option<i64>,
+    pub filename: String,
+    pub sha256: String,
+    pub size: i64,
+    pub mode: AttachmentMode,
+    pub created_at: String,
+}
+
+fn attachment_from_row(row: &Row) -> rusqlite::Result<Attachment> {
+    let mode: String = row.get(6)?;
+    Ok(Attachment {
+        id: row.get(0)?,
+        chat_id: row.get(1)?,
+        message_id: row.get(2)?,
+        filename: row.get(3)?,
+        sha256: row.get(4)?,
+        size: row.get(5)?,
+        mode: AttachmentMode::parse(&mode).unwrap_or(AttachmentMode::Indexed),
+        created_at: row.get(7)?,
+    })
+}
+
+fn attachments_dir() -> Result<PathBuf, AppError> {
+    let db_path = get_db_path()?;
+    db_path.parent()
+        .map(|workspace| workspace.join(ATTACHMENTS_DIR))
+        .ok_or_else(|| AppError::internal("Failed to get workspace directory"))
+}
+
+fn stored_path(dir: &Path, sha256: &str) -> PathBuf {
+    dir.join(&sha256[..2]).join(sha256)
+}
+
+fn content_hash(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+// Writes `contents` under its hash unless the same content is already stored.
+fn store_contents(sha256: &str, contents: &[u8]) -> Result<(), AppError> {
+    let path = stored_path(&attachments_dir()?, sha256);
+    if path.exists() {
+        return Ok(());
+    }
+
+    let parent = path.parent()
+        .ok_or_else(|| AppError::internal("Failed to get attachment directory"))?;
+    fs::create_dir_all(parent)
+        .map_err(|e| AppError::with_cause("Failed to create attachment directory", e))?;
+
+    // Written under a temporary name first so a stored file is always complete
+    let partial = parent.join(format!(".{}.tmp", sha256));
+    fs::write(&partial, contents)
+        .map_err(|e| AppError::with_cause("Failed to store attachment", e))?;
+    fs::rename(&partial, &path)
+        .map_err(|e| AppError::with_cause("Failed to store attachment", e))
+}
+
+fn extract_text(filename: &str, contents: &[u8]) -> Result<String, AppError> {
+    let is_pdf = Path::new(filename)
+        .extension()
+        .map(|extension| extension.eq_ignore_ascii_case("pdf"))
+        .unwrap_or(false);
+
+    let text = if is_pdf {
+        let doc = PdfDocument::load_mem(contents)
+            .map_err(|e| AppError::invalid_input(format!("Failed to read PDF {}: {}", filename, e)))?;
+
+        let mut text = String::new();
+        for page_num in doc.get_pages().keys() {
+            if let Ok(page_text) = doc.extract_text(&[*page_num]) {
+                text.push_str(&page_text);
+                text.push('\n');
+            }
+        }
+        text
+    } else {
+        String::from_utf8(contents.to_vec())
+            .map_err(|_| AppError::invalid_input(format!("{} is not a text file or PDF", filename)))?
+    };
+
+    if text.trim().is_empty() {
+        return Err(AppError::invalid_input(format!("{} contains no text", filename)));
+    }
+
+    Ok(text)
+}
+
+// Splits text at line breaks. Joining the chunks gives back the original text.
+fn chunk_text(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_chars = 0;
+
+    for line in text.split_inclusive('\n') {
+        let line_chars = line.chars().count();
+        if current_chars > 0 && current_chars + line_chars > CHUNK_CHARS {
+            chunks.push(std::mem::take(&mut current));
+            current_chars = 0;
+        }
+
+        current.push_str(line);
+        current_chars += line_chars;
+
+        // A line longer than a chunk is cut wherever the chunk is full
+        while current_chars > CHUNK_CHARS {
+            let split = current.char_indices().nth(CHUNK_CHARS).map(|(i, _)| i).unwrap_or(current.len());
+            let rest = current.split_off(split);
+            chunks.push(std::mem::replace(&mut current, rest));
+            current_chars -= CHUNK_CHARS;
+        }
+    }
+
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+pub fn get_attachment(conn: &Connection, attachment_id: i64) -> Result<Attachment, AppError> {
+    let mut stmt = conn.prepare_cached(&format!("{} WHERE id = ?1", ATTACHMENT_SELECT))
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    stmt.query_row([attachment_id], attachment_from_row)
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::not_found(format!("Attachment {} not found", attachment_id)),
+            e => AppError::with_cause("Failed to get attachment", e),
+        })
+}
+
+// Stores a file for the next message of a chat. The attachment stays pending until
+// link_attachments ties it to the message once that is saved. Without a mode, short
+// text is inlined and longer text is indexed.
+pub fn add_attachment(conn: &Connection, chat_id: i64, source: &Path, mode: Option<AttachmentMode>) -> Result<Attachment, AppError> {
+    check_chat_writable(conn, chat_id)?;
+
+    let filename = source.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| AppError::invalid_input(format!("{} is not a file", source.display())))?;
+
+    let size = fs::metadata(source)
+        .map_err(|e| AppError::with_cause(format!("Failed to read {}", filename), e))?
+        .len();
+
+    if size > MAX_ATTACHMENT_BYTES {
+        return Err(AppError::invalid_input(format!(
+            "{} is {} MB, attachments can be at most {} MB",
+            filename, size / (1024 * 1024), MAX_ATTACHMENT_BYTES / (1024 * 1024)
+        )));
+    }
+
+    let contents = fs::read(source)
+        .map_err(|e| AppError::with_cause(format!("Failed to read {}", filename), e))?;
+    let text = extract_text(&filename, &contents)?;
+
+    let too_long = text.chars().count() > MAX_INLINE_CHARS;
+    let mode = match mode {
+        Some(AttachmentMode::Inline) if too_long => {
+            return Err(AppError::invalid_input(format!(
+                "{} is too long to include in the prompt, index it instead", filename
+            )));
+        }
+        Some(mode) => mode,
+        None if too_long => AttachmentMode::Indexed,
+        None => AttachmentMode::Inline,
+    };
+
+    let sha256 = content_hash(&contents);
+
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| AppError::with_cause("Failed to start transaction", e))?;
+
+    tx.execute(
+        "INSERT INTO attachments (chat_id, filename, sha256, size, mode, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![chat_id, filename, sha256, contents.len() as i64, mode.as_str(), current_timestamp(&tx)?],
+    ).map_err(|e| AppError::with_cause("Failed to save attachment", e))?;
+
+    let attachment_id = tx.last_insert_rowid();
+
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT INTO attachment_chunks (attachment_id, position, text) VALUES (?1, ?2, ?3)"
+        ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+        for (position, chunk) in chunk_text(&text).iter().enumerate() {
+            stmt.execute(params![attachment_id, position as i64, chunk])
+                .map_err(|e| AppError::with_cause("Failed to save attachment text", e))?;
+        }
+    }
+
+    // Only stored once the rows are in place, and removed again if they can't be committed
+    store_contents(&sha256, &contents)?;
+
+    if let Err(e) = tx.commit() {
+        if let Err(e) = remove_unused_attachment_files(conn) {
+            log::warn!("Failed to remove unused attachment files: {}", e);
+        }
+        return Err(AppError::with_cause("Failed to save attachment", e));
+    }
+
+    get_attachment(conn, attachment_id)
+}
+
+// Ties pending attachments of the message's chat to the message.
+pub fn link_attachments(conn: &Connection, message_id: i64, attachment_ids: &[i64]) -> Result<(), AppError> {
+    let message = get_message(conn, message_id)?;
+
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| AppError::with_cause("Failed to start transaction", e))?;
+
+    for attachment_id in attachment_ids {
+        let linked = tx.execute(
+            "UPDATE attachments SET message_id = ?1
+             WHERE id = ?2 AND chat_id = ?3 AND message_id IS NULL",
+            params![message_id, attachment_id, message.chat_id],
+        ).map_err(|e| AppError::with_cause("Failed to link attachment", e))?;
+
+        if linked == 0 {
+            return Err(AppError::invalid_input(format!(
+                "Attachment {} is not a pending attachment of chat {}", attachment_id, message.chat_id
+            )));
+        }
+    }
+
+    tx.commit()
+        .map_err(|e| AppError::with_cause("Failed to link attachments", e))
+}
+
+// Gives the copy of a message its own attachment rows, sharing the stored files.
+pub fn copy_message_attachments(conn: &Connection, message_id: i64, chat_id: i64, copy_id: i64) -> Result<(), AppError> {
+    let mut stmt = conn.prepare_cached("SELECT id FROM attachments WHERE message_id = ?1 ORDER BY id")
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let attachment_ids = stmt.query_map([message_id], |row| row.get::<_, i64>(0))
+        .map_err(|e| AppError::with_cause("Failed to query attachments", e))?
+        .collect::<Result<Vec<i64>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect attachments", e))?;
+
+    for attachment_id in attachment_ids {
+        conn.execute(
+            "INSERT INTO attachments (chat_id, message_id, filename, sha256, size, mode, created_at)
+             SELECT ?1, ?2, filename, sha256, size, mode, created_at FROM attachments WHERE id = ?3",
+            params![chat_id, copy_id, attachment_id],
+        ).map_err(|e| AppError::with_cause("Failed to copy attachment", e))?;
+
+        conn.execute(
+            "INSERT INTO attachment_chunks (attachment_id, position, text)
+             SELECT ?1, position, text FROM attachment_chunks WHERE attachment_id = ?2",
+            params![conn.last_insert_rowid(), attachment_id],
+        ).map_err(|e| AppError::with_cause("Failed to copy attachment text", e))?;
+    }
+
+    Ok(())
+}
+
+// Deletes stored files that no attachment refers to any more, after attachments were
+// removed or their chats were purged.
+pub fn remove_unused_attachment_files(conn: &Connection) -> Result<usize, AppError> {
+    let dir = attachments_dir()?;
+    if !dir.exists() {
+        return Ok(0);
+    }
+
+    let mut stmt = conn.prepare_cached("SELECT DISTINCT sha256 FROM attachments")
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let used = stmt.query_map([], |row| row.get::<_, String>(0))
+        .map_err(|e| AppError::with_cause("Failed to query attachments", e))?
+        .collect::<Result<HashSet<String>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect attachments", e))?;
+
+    let mut removed = 0;
+    for prefix in fs::read_dir(&dir).map_err(|e| AppError::with_cause("Failed to read attachments", e))? {
+        let prefix = prefix.map_err(|e| AppError::with_cause("Failed to read attachments", e))?.path();
+        if !prefix.is_dir() {
+            continue;
+        }
+
+        for file in fs::read_dir(&prefix).map_err(|e| AppError::with_cause("Failed to read attachments", e))? {
+            let file = file.map_err(|e| AppError::with_cause("Failed to read attachments", e))?;
+            let name = file.file_name().to_string_lossy().to_string();
+            if used.contains(&name) {
+                continue;
+            }
+
+            fs::remove_file(file.path())
+                .map_err(|e| AppError::with_cause(format!("Failed to remove attachment {}", name), e))?;
+            removed += 1;
+        }
+
+        // Only succeeds once the folder is empty
+        let _ = fs::remove_dir(&prefix);
+    }
+
+    Ok(removed)
+}
+
+pub fn remove_attachment(conn: &Connection, attachment_id: i64) -> Result<(), AppError> {
+    let removed = conn.execute("DELETE FROM attachments WHERE id = ?1", [attachment_id])
+        .map_err(|e| AppError::with_cause("Failed to remove attachment", e))?;
+
+    if removed == 0 {
+        return Err(AppError::not_found(format!("Attachment {} not found", attachment_id)));
+    }
+
+    remove_unused_attachment_files(conn)?;
+    Ok(())
+}
+
+// Every attachment of a chat, pending ones included, oldest first.
+pub fn list_chat_attachments(conn: &Connection, chat_id: i64) -> Result<Vec<Attachment>, AppError> {
+    let mut stmt = conn.prepare_cached(&format!("{} WHERE chat_id = ?1 ORDER BY id", ATTACHMENT_SELECT))
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let attachments = stmt.query_map([chat_id], attachment_from_row)
+        .map_err(|e| AppError::with_cause("Failed to query attachments", e))?;
+
+    attachments.collect::<Result<Vec<Attachment>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect attachments", e))
+}
+
+// Fills in the attachments of already loaded messages.
+pub fn attach_message_attachments(conn: &Connection, messages: &mut [Message]) -> Result<(), AppError> {
+    let mut stmt = conn.prepare_cached(&format!("{} WHERE message_id = ?1 ORDER BY id", ATTACHMENT_SELECT))
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    for message in messages.iter_mut() {
+        let message_id = match message.id {
+            Some(id) => id,
+            None => continue,
+        };
+
+        message.attachments = stmt.query_map([message_id], attachment_from_row)
+            .map_err(|e| AppError::with_cause("Failed to query attachments", e))?
+            .collect::<Result<Vec<Attachment>, _>>()
+            .map_err(|e| AppError::with_cause("Failed to collect attachments", e))?;
+    }
+
+    Ok(())
+}
+
+pub fn get_attachment_text(conn: &Connection, attachment_id: i64) -> Result<String, AppError> {
+    get_attachment(conn, attachment_id)?;
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT text FROM attachment_chunks WHERE attachment_id = ?1 ORDER BY position"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let chunks = stmt.query_map([attachment_id], |row| row.get::<_, String>(0))
+        .map_err(|e| AppError::with_cause("Failed to query attachment text", e))?;
+
+    chunks.collect::<Result<String, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect attachment text", e))
+}
+
+// Any word of the query may match, unlike message search where all of them must.
+fn retrieval_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() > 1)
+        .map(|word| format!("\"{}\"", word))
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" OR "))
+    }
+}
+
+// Chunks of the chat's indexed attachments that best match `query`, as
+// (text, filename) pairs ready for prepare_sources.
+pub fn retrieve_attachment_context(conn: &Connection, chat_id: i64, query: &str, limit: u32) -> Result<Vec<(String, String)>, AppError> {
+    let fts_query = match retrieval_query(query) {
+        Some(fts_query) => fts_query,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT ch.text, a.filename
+         FROM attachment_chunks_fts
+         JOIN attachment_chunks ch ON ch.id = attachment_chunks_fts.rowid
+         JOIN attachments a ON a.id = ch.attachment_id
+         WHERE attachment_chunks_fts MATCH ?1 AND a.chat_id = ?2 AND a.mode = 'indexed'
+         ORDER BY bm25(attachment_chunks_fts)
+         LIMIT ?3"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let contexts = stmt.query_map(params![fts_query, chat_id, limit], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| AppError::with_cause("Failed to search attachments", e))?;
+
+    contexts.collect::<Result<Vec<(String, String)>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect attachment chunks", e))
+}
+
+#[tauri::command]
+pub async fn add_attachment_command(
+    app: AppHandle,
+    chat_id: i64,
+    path: String,
+    mode: Option<AttachmentMode>,
+) -> Result<Attachment, AppError> {
+    tokio::task::spawn_blocking(move || {
+        let db = app.state::<Db>();
+        let conn = db.conn()?;
+
+        // Files are stored as they are, which would leave them readable next to an encrypted database
+        if conn.passphrase().is_some() {
+            return Err(AppError::invalid_input("Attachments can't be added while the database is encrypted"));
+        }
+
+        add_attachment(&conn, chat_id, Path::new(&path), mode)
+    })
+        .await
+        .map_err(|e| AppError::with_cause("Adding attachment failed", e))?
+}
+
+#[tauri::command]
+pub fn link_attachments_command(db: State<'_, Db>, message_id: i64, attachment_ids: Vec<i64>) -> Result<(), AppError> {
+    link_attachments(&*db.conn()?, message_id, &attachment_ids)
+}
+
+#[tauri::command]
+pub fn remove_attachment_command(db: State<'_, Db>, attachment_id: i64) -> Result<(), AppError> {
+    remove_attachment(&*db.conn()?, attachment_id)
+}
+
+#[tauri::command]
+pub fn list_chat_attachments_command(db: State<'_, Db>, chat_id: i64) -> Result<Vec<Attachment>, AppError> {
+    list_chat_attachments(&*db.conn()?, chat_id)
+}
+
+#[tauri::command]
+pub fn get_attachment_text_command(db: State<'_, Db>, attachment_id: i64) -> Result<String, AppError> {
+    get_attachment_text(&*db.conn()?, attachment_id)
+}
+
+#[tauri::command]
+pub fn search_chat_attachments_command(
+    db: State<'_, Db>,
+    chat_id: i64,
+    query: String,
+    limit: Option<u32>,
+) -> Result<Vec<RetrievedChunk>, AppError> {
+    let conn = db.conn()?;
+    let contexts = retrieve_attachment_context(&conn, chat_id, &query, limit.unwrap_or(DEFAULT_ATTACHMENT_SEARCH_LIMIT))?;
+    let action = get_chat_settings(&conn, chat_id)?.rag.injection_action;
+    Ok(prepare_sources(contexts, action))
+}
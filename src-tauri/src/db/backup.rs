@@ -0,0 +1,818 @@
+use lazy_static::lazy_static;
+use rusqlite::backup::Backup;
+use rusqlite::Connection;
+use serde::{Serialize, Deserialize};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, TryLockError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Manager, State};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use crate::db::db::{current_timestamp, get_db_path, Db};
+use crate::db::encryption::{apply_key, is_encrypted};
+use crate::db::migrations::{latest_version, run_migrations, schema_version};
+use crate::db::settings::{get_setting, set_setting};
+use crate::error::{AppError, ErrorCode};
+
+// A backup is a zip archive of the LevChat workspace:
+//
+//   manifest.json     BackupManifest, listing every other file with its size
+//   chats.db          consistent snapshot taken with the SQLite online backup API,
+//                     encrypted with the same key as the live database
+//   <settings files>  top-level files of the workspace
+//   data/...          documents used for RAG
+//   attachments/...   files attached to messages
+//   index/...         RAG index, only with include_index
+//   model/...         language and embedding models, unless exclude_models is set
+//   em_model/...
+//
+// The setup folder (llama.cpp binaries) and the backups folder are never included, and a
+// backup that lists anything outside the entries above is refused.
+pub const BACKUP_FORMAT: &str = "levchat-backup";
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_NAME: &str = "manifest.json";
+const DATABASE_NAME: &str = "chats.db";
+const DATA_DIR: &str = "data";
+const INDEX_DIR: &str = "index";
+const ATTACHMENTS_DIR: &str = "attachments";
+const MODEL_DIRS: [&str; 2] = ["model", "em_model"];
+const BACKUP_DIR: &str = "backups";
+const PREVIOUS_DATABASE_PREFIX: &str = "chats-before-restore-";
+// Copies of the database replaced by a restore that are kept
+const PREVIOUS_DATABASES_KEPT: usize = 3;
+
+const MANUAL_PREFIX: &str = "levchat-backup-";
+const SCHEDULED_PREFIX: &str = "levchat-auto-backup-";
+const SCHEDULE_KEY: &str = "backup_schedule";
+const SCHEDULER_CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+// Pages copied per step of the online backup
+const BACKUP_PAGES_PER_STEP: i32 = 1024;
+
+lazy_static! {
+    // Keeps a scheduled backup from running while a restore replaces files, and vice versa
+    static ref BACKUP_LOCK: Mutex<()> = Mutex::new(());
+}
+
+fn lock_backups() -> Result<MutexGuard<'static, ()>, AppError> {
+    match BACKUP_LOCK.try_lock() {
+        Ok(guard) => Ok(guard),
+        Err(TryLockError::WouldBlock) => Err(AppError::busy("A backup or restore is already running")),
+        // The lock guards no data, a backup that panicked leaves nothing to repair
+        Err(TryLockError::Poisoned(e)) => Ok(e.into_inner()),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupOptions {
+    pub include_index: bool,
+    // Model files are large and can be downloaded again
+    pub exclude_models: bool,
+}
+
+impl Default for BackupOptions {
+    fn default() -> Self {
+        BackupOptions {
+            include_index: true,
+            exclude_models: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupSchedule {
+    pub enabled: bool,
+    pub interval_hours: u32,
+    // Scheduled backups kept, older ones are deleted. Manual backups are never rotated.
+    pub keep: usize,
+    pub options: BackupOptions,
+}
+
+impl Default for BackupSchedule {
+    fn default() -> Self {
+        BackupSchedule {
+            enabled: false,
+            interval_hours: 24,
+            keep: 7,
+            options: BackupOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupFile {
+    pub path: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub format: String,
+    pub version: u32,
+    pub created_at: String,
+    pub app_version: String,
+    pub schema_version: i64,
+    pub includes_index: bool,
+    pub includes_models: bool,
+    #[serde(default)]
+    pub encrypted: bool,
+    pub files: Vec<BackupFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub path: String,
+    pub size: u64,
+    pub scheduled: bool,
+    pub manifest: BackupManifest,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RestoreReport {
+    pub files_restored: usize,
+    // Copy of chats.db taken just before it was replaced
+    pub previous_database: String,
+    pub manifest: BackupManifest,
+}
+
+fn workspace_dir() -> Result<PathBuf, AppError> {
+    let db_path = get_db_path()?;
+    db_path.parent()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| AppError::internal("Failed to get workspace directory"))
+}
+
+fn backup_dir() -> Result<PathBuf, AppError> {
+    let dir = workspace_dir()?.join(BACKUP_DIR);
+    fs::create_dir_all(&dir)
+        .map_err(|e| AppError::with_cause("Failed to create backup directory", e))?;
+    Ok(dir)
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Adds every file below `dir` to `files` as (absolute path, archive path).
+fn collect_files(dir: &Path, archive_prefix: &str, files: &mut Vec<(PathBuf, String)>) -> Result<(), AppError> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    let entries = fs::read_dir(dir)
+        .map_err(|e| AppError::with_cause(format!("Failed to read {}", dir.display()), e))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|e| AppError::with_cause(format!("Failed to read {}", dir.display()), e))?;
+        let path = entry.path();
+        let archive_path = format!("{}/{}", archive_prefix, entry.file_name().to_string_lossy());
+
+        if path.is_dir() {
+            collect_files(&path, &archive_path, files)?;
+        } else {
+            files.push((path, archive_path));
+        }
+    }
+
+    Ok(())
+}
+
+// Workspace files that go into a backup, except chats.db which is snapshotted separately.
+fn workspace_files(workspace: &Path, options: &BackupOptions) -> Result<Vec<(PathBuf, String)>, AppError> {
+    let mut files = Vec::new();
+
+    let entries = fs::read_dir(workspace)
+        .map_err(|e| AppError::with_cause("Failed to read workspace", e))?;
+
+    // Settings live next to chats.db
+    for entry in entries {
+        let entry = entry.map_err(|e| AppError::with_cause("Failed to read workspace", e))?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.path().is_file() && !name.starts_with(DATABASE_NAME) {
+            files.push((entry.path(), name));
+        }
+    }
+
+    collect_files(&workspace.join(DATA_DIR), DATA_DIR, &mut files)?;
+    collect_files(&workspace.join(ATTACHMENTS_DIR), ATTACHMENTS_DIR, &mut files)?;
+
+    if options.include_index {
+        collect_files(&workspace.join(INDEX_DIR), INDEX_DIR, &mut files)?;
+    }
+
+    if !options.exclude_models {
+        for dir in MODEL_DIRS {
+            collect_files(&workspace.join(dir), dir, &mut files)?;
+        }
+    }
+
+    Ok(files)
+}
+
+// Whether a restore may write `archive_path` into the workspace: chats.db, the settings
+// files next to it and the folders a backup is made of.
+fn is_restorable(archive_path: &str) -> bool {
+    let names: Option<Vec<&str>> = Path::new(archive_path).components()
+        .map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect();
+
+    match names.as_deref() {
+        Some([name]) => *name == DATABASE_NAME || !name.starts_with(DATABASE_NAME),
+        Some([dir, _, ..]) => [DATA_DIR, ATTACHMENTS_DIR, INDEX_DIR].contains(dir) || MODEL_DIRS.contains(dir),
+        _ => false,
+    }
+}
+
+fn is_model_file(archive_path: &str) -> bool {
+    MODEL_DIRS.iter().any(|dir| archive_path.starts_with(&format!("{}/", dir)))
+}
+
+// Copies the live database into `target` and returns its schema version and a timestamp.
+fn snapshot_database(db: &Db, target: &Path) -> Result<(i64, String), AppError> {
+    let conn = db.conn()?;
+
+    let mut snapshot = Connection::open(target)
+        .map_err(|e| AppError::with_cause("Failed to create database snapshot", e))?;
+
+    if let Some(passphrase) = conn.passphrase() {
+        snapshot.pragma_update(None, "key", passphrase)
+            .map_err(|e| AppError::with_cause("Failed to set snapshot key", e))?;
+    }
+
+    Backup::new(&conn, &mut snapshot)
+        .and_then(|backup| backup.run_to_completion(BACKUP_PAGES_PER_STEP, Duration::ZERO, None))
+        .map_err(|e| AppError::with_cause("Failed to back up database", e))?;
+
+    Ok((schema_version(&conn)?, current_timestamp(&conn)?))
+}
+
+fn write_archive(
+    archive_path: &Path,
+    snapshot: &Path,
+    files: &[(PathBuf, String)],
+    manifest: &BackupManifest,
+) -> Result<(), AppError> {
+    let file = File::create(archive_path)
+        .map_err(|e| AppError::with_cause("Failed to create backup file", e))?;
+    let mut zip = ZipWriter::new(file);
+
+    let compressed = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .large_file(true);
+    // Model weights barely compress, storing them is much faster
+    let stored = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .large_file(true);
+
+    let manifest_json = serde_json::to_vec_pretty(manifest)
+        .map_err(|e| AppError::with_cause("Failed to serialize backup manifest", e))?;
+    zip.start_file(MANIFEST_NAME, compressed)
+        .map_err(|e| AppError::with_cause("Failed to write backup manifest", e))?;
+    io::Write::write_all(&mut zip, &manifest_json)
+        .map_err(|e| AppError::with_cause("Failed to write backup manifest", e))?;
+
+    let database = std::iter::once((snapshot.to_path_buf(), DATABASE_NAME.to_string()));
+    for (path, archive_name) in database.chain(files.iter().cloned()) {
+        let options = if is_model_file(&archive_name) { stored } else { compressed };
+
+        zip.start_file(archive_name.as_str(), options)
+            .map_err(|e| AppError::with_cause(format!("Failed to add {} to backup", archive_name), e))?;
+
+        let mut source = File::open(&path)
+            .map_err(|e| AppError::with_cause(format!("Failed to read {}", path.display()), e))?;
+        io::copy(&mut source, &mut zip)
+            .map_err(|e| AppError::with_cause(format!("Failed to add {} to backup", archive_name), e))?;
+    }
+
+    zip.finish()
+        .map_err(|e| AppError::with_cause("Failed to finish backup archive", e))?;
+
+    Ok(())
+}
+
+fn build_manifest(
+    snapshot: &Path,
+    files: &[(PathBuf, String)],
+    options: &BackupOptions,
+    schema_version: i64,
+    created_at: String,
+) -> Result<BackupManifest, AppError> {
+    let mut manifest_files = Vec::new();
+    let database = std::iter::once((snapshot.to_path_buf(), DATABASE_NAME.to_string()));
+
+    for (path, archive_name) in database.chain(files.iter().cloned()) {
+        let size = fs::metadata(&path)
+            .map_err(|e| AppError::with_cause(format!("Failed to read {}", path.display()), e))?
+            .len();
+        manifest_files.push(BackupFile { path: archive_name, size });
+    }
+
+    Ok(BackupManifest {
+        format: BACKUP_FORMAT.to_string(),
+        version: BACKUP_FORMAT_VERSION,
+        created_at,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        schema_version,
+        includes_index: options.include_index,
+        includes_models: !options.exclude_models,
+        encrypted: is_encrypted(snapshot)?,
+        files: manifest_files,
+    })
+}
+
+pub fn create_backup(db: &Db, options: &BackupOptions, scheduled: bool) -> Result<BackupInfo, AppError> {
+    let _guard = lock_backups()?;
+
+    let workspace = workspace_dir()?;
+    let backups = backup_dir()?;
+    let stamp = unix_secs();
+
+    let prefix = if scheduled { SCHEDULED_PREFIX } else { MANUAL_PREFIX };
+    let archive_path = backups.join(format!("{}{}.zip", prefix, stamp));
+    // Written under a temporary name so an interrupted backup never looks complete
+    let partial_path = archive_path.with_extension("zip.part");
+    let snapshot = backups.join(format!(".snapshot-{}.db", stamp));
+
+    let manifest = snapshot_database(db, &snapshot)
+        .and_then(|(schema_version, created_at)| {
+            let files = workspace_files(&workspace, options)?;
+            let manifest = build_manifest(&snapshot, &files, options, schema_version, created_at)?;
+            write_archive(&partial_path, &snapshot, &files, &manifest)?;
+            Ok(manifest)
+        });
+
+    let _ = fs::remove_file(&snapshot);
+    let manifest = match manifest {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            let _ = fs::remove_file(&partial_path);
+            return Err(e);
+        }
+    };
+
+    fs::rename(&partial_path, &archive_path)
+        .map_err(|e| AppError::with_cause("Failed to finish backup", e))?;
+
+    Ok(BackupInfo {
+        size: fs::metadata(&archive_path).map(|m| m.len()).unwrap_or(0),
+        path: archive_path.display().to_string(),
+        scheduled,
+        manifest,
+    })
+}
+
+fn read_manifest(archive: &mut ZipArchive<File>) -> Result<BackupManifest, AppError> {
+    let entry = archive.by_name(MANIFEST_NAME)
+        .map_err(|_| AppError::invalid_input("Not a LevChat backup: manifest.json is missing"))?;
+
+    let manifest: BackupManifest = serde_json::from_reader(entry)
+        .map_err(|e| AppError::with_cause("Invalid backup manifest", e))?;
+
+    if manifest.format != BACKUP_FORMAT {
+        return Err(AppError::invalid_input(format!("Not a LevChat backup (format is \"{}\")", manifest.format)));
+    }
+
+    if manifest.version > BACKUP_FORMAT_VERSION {
+        return Err(AppError::invalid_input(format!(
+            "Backup format version {} is newer than this version of LevChat supports",
+            manifest.version
+        )));
+    }
+
+    Ok(manifest)
+}
+
+fn open_archive(path: &Path) -> Result<ZipArchive<File>, AppError> {
+    let file = File::open(path)
+        .map_err(|e| AppError::with_cause("Failed to open backup", e))?;
+    ZipArchive::new(file)
+        .map_err(|e| AppError::with_cause("Failed to read backup archive", e))
+}
+
+// Checks that the archive is complete and readable without touching the workspace.
+// Every entry is read in full, which verifies its checksum.
+pub fn validate_backup(path: &Path) -> Result<BackupManifest, AppError> {
+    let mut archive = open_archive(path)?;
+    let manifest = read_manifest(&mut archive)?;
+
+    if manifest.schema_version > latest_version() {
+        return Err(AppError::invalid_input(format!(
+            "The backup uses database schema version {} but this version of LevChat only supports up to version {}",
+            manifest.schema_version,
+            latest_version()
+        )));
+    }
+
+    if !manifest.files.iter().any(|file| file.path == DATABASE_NAME) {
+        return Err(AppError::invalid_input("Backup does not contain chats.db"));
+    }
+
+    for file in &manifest.files {
+        let mut entry = archive.by_name(&file.path)
+            .map_err(|_| AppError::invalid_input(format!("Backup is incomplete: {} is missing", file.path)))?;
+
+        if entry.enclosed_name().is_none() || !is_restorable(&file.path) {
+            return Err(AppError::invalid_input(format!("Backup contains an unsafe path: {}", file.path)));
+        }
+
+        let size = io::copy(&mut entry, &mut io::sink())
+            .map_err(|e| AppError::with_cause(format!("Backup is corrupted: {} could not be read", file.path), e))?;
+
+        if size != file.size {
+            return Err(AppError::invalid_input(format!(
+                "Backup is corrupted: {} is {} bytes, expected {}",
+                file.path, size, file.size
+            )));
+        }
+    }
+
+    Ok(manifest)
+}
+
+fn extract_archive(path: &Path, manifest: &BackupManifest, staging: &Path) -> Result<(), AppError> {
+    let mut archive = open_archive(path)?;
+
+    for file in &manifest.files {
+        let mut entry = archive.by_name(&file.path)
+            .map_err(|e| AppError::with_cause(format!("Failed to read {} from backup", file.path), e))?;
+        let relative = entry.enclosed_name()
+            .ok_or_else(|| AppError::invalid_input(format!("Backup contains an unsafe path: {}", file.path)))?;
+
+        let target = staging.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| AppError::with_cause("Failed to create directory", e))?;
+        }
+
+        let mut output = File::create(&target)
+            .map_err(|e| AppError::with_cause(format!("Failed to extract {}", file.path), e))?;
+        io::copy(&mut entry, &mut output)
+            .map_err(|e| AppError::with_cause(format!("Failed to extract {}", file.path), e))?;
+    }
+
+    Ok(())
+}
+
+// Opens the database from a backup with the key of the live one. The backup API only
+// copies between databases that are both unencrypted or both use the same key.
+fn check_database(path: &Path, passphrase: Option<&str>) -> Result<Connection, AppError> {
+    match (is_encrypted(path)?, passphrase) {
+        (true, None) => return Err(AppError::invalid_input("Backup is encrypted but the current database is not")),
+        (false, Some(_)) => return Err(AppError::invalid_input("Backup is not encrypted but the current database is")),
+        _ => {}
+    }
+
+    let conn = Connection::open(path)
+        .map_err(|e| AppError::with_cause("Failed to open database from backup", e))?;
+
+    if let Some(passphrase) = passphrase {
+        apply_key(&conn, passphrase)
+            .map_err(|_| AppError::new(ErrorCode::WrongPassphrase, "Backup was encrypted with a different passphrase"))?;
+    }
+
+    let integrity: String = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))
+        .map_err(|e| AppError::with_cause("Failed to check database from backup", e))?;
+
+    if integrity != "ok" {
+        return Err(AppError::invalid_input(format!("Database in backup is damaged: {}", integrity)));
+    }
+
+    Ok(conn)
+}
+
+// Moves the staged files other than chats.db into the workspace. The files they replace are
+// moved to `replaced` first and every path moved is added to `moved`, so that
+// `put_back_files` can undo it.
+fn move_staged_files(
+    manifest: &BackupManifest,
+    staging: &Path,
+    workspace: &Path,
+    replaced: &Path,
+    moved: &mut Vec<String>,
+) -> Result<(), AppError> {
+    for file in manifest.files.iter().filter(|file| file.path != DATABASE_NAME) {
+        let source = staging.join(&file.path);
+        let target = workspace.join(&file.path);
+
+        for dir in [target.parent(), replaced.join(&file.path).parent()].into_iter().flatten() {
+            fs::create_dir_all(dir)
+                .map_err(|e| AppError::with_cause("Failed to create directory", e))?;
+        }
+
+        if target.exists() {
+            fs::rename(&target, replaced.join(&file.path))
+                .map_err(|e| AppError::with_cause(format!("Failed to restore {}", file.path), e))?;
+        }
+        moved.push(file.path.clone());
+
+        fs::rename(&source, &target)
+            .map_err(|e| AppError::with_cause(format!("Failed to restore {}", file.path), e))?;
+    }
+
+    Ok(())
+}
+
+// Undoes `move_staged_files` after a failed restore. Problems are only logged, the error
+// that stopped the restore is the one reported.
+fn put_back_files(moved: &[String], workspace: &Path, replaced: &Path) {
+    for path in moved.iter().rev() {
+        let target = workspace.join(path);
+        let previous = replaced.join(path);
+
+        let result = if previous.exists() {
+            fs::rename(&previous, &target)
+        } else {
+            fs::remove_file(&target)
+        };
+        if let Err(e) = result {
+            log::error!("Failed to put back {} after a failed restore: {}", path, e);
+        }
+    }
+}
+
+// Copies the staged database into the live one and brings it up to date.
+fn swap_database(conn: &mut Connection, restored: &Connection) -> Result<(), AppError> {
+    Backup::new(restored, conn)
+        .and_then(|backup| backup.run_to_completion(BACKUP_PAGES_PER_STEP, Duration::ZERO, None))
+        .map_err(|e| AppError::with_cause("Failed to restore database", e))?;
+
+    // Backups from older versions are brought up to date right away
+    run_migrations(conn, &get_db_path()?)?;
+    conn.pragma_update(None, "foreign_keys", true)
+        .map_err(|e| AppError::with_cause("Failed to enable foreign keys", e))
+}
+
+// Moves the staged files into the workspace, then swaps the live database for the staged
+// one, keeping a copy of the current database in `previous_database`. If any step fails
+// the workspace and the database are put back as they were.
+fn replace_workspace(
+    db: &Db,
+    manifest: &BackupManifest,
+    staging: &Path,
+    workspace: &Path,
+    previous_database: &Path,
+) -> Result<usize, AppError> {
+    let mut conn = db.conn()?;
+    let passphrase = conn.passphrase().map(str::to_string);
+    let restored = check_database(&staging.join(DATABASE_NAME), passphrase.as_deref())?;
+
+    conn.execute("VACUUM INTO ?1", [previous_database.to_string_lossy()])
+        .map_err(|e| AppError::with_cause("Failed to save current database before restoring", e))?;
+
+    let replaced = staging.with_extension("replaced");
+    let mut moved = Vec::new();
+    let result = move_staged_files(manifest, staging, workspace, &replaced, &mut moved)
+        .and_then(|_| swap_database(&mut conn, &restored));
+
+    if let Err(e) = result {
+        put_back_files(&moved, workspace, &replaced);
+
+        // The swap may have failed halfway, so the copy taken above goes back in as well
+        let put_back = check_database(previous_database, passphrase.as_deref())
+            .and_then(|previous| swap_database(&mut conn, &previous));
+        if let Err(put_back_error) = put_back {
+            log::error!(
+                "Failed to put back the database after a failed restore, it is saved in {}: {}",
+                previous_database.display(), put_back_error
+            );
+        }
+
+        let _ = fs::remove_dir_all(&replaced);
+        return Err(e);
+    }
+
+    let _ = fs::remove_dir_all(&replaced);
+    Ok(moved.len() + 1)
+}
+
+// Deletes the oldest copies of databases replaced by a restore so that at most
+// PREVIOUS_DATABASES_KEPT remain.
+fn rotate_previous_databases() -> Result<usize, AppError> {
+    let entries = fs::read_dir(backup_dir()?)
+        .map_err(|e| AppError::with_cause("Failed to read backup directory", e))?;
+
+    let mut previous: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension() == Some(OsStr::new("db"))
+                && path.file_name().map(|name| name.to_string_lossy().starts_with(PREVIOUS_DATABASE_PREFIX)).unwrap_or(false)
+        })
+        .collect();
+
+    // Names end in a unix timestamp, so this sorts oldest first
+    previous.sort();
+    let excess = previous.len().saturating_sub(PREVIOUS_DATABASES_KEPT);
+
+    for path in &previous[..excess] {
+        fs::remove_file(path)
+            .map_err(|e| AppError::with_cause(format!("Failed to delete old backup {}", path.display()), e))?;
+    }
+
+    Ok(excess)
+}
+
+// Replaces the workspace with the contents of a backup. The archive is validated and
+// extracted to a staging folder first, so a bad backup leaves everything untouched.
+// Files that are not in the backup, such as models left out of it, are kept.
+pub fn restore_backup(db: &Db, path: &Path) -> Result<RestoreReport, AppError> {
+    let _guard = lock_backups()?;
+
+    let manifest = validate_backup(path)?;
+
+    let workspace = workspace_dir()?;
+    let backups = backup_dir()?;
+    let stamp = unix_secs();
+    let staging = backups.join(format!(".restore-{}", stamp));
+    let previous_database = backups.join(format!("{}{}.db", PREVIOUS_DATABASE_PREFIX, stamp));
+
+    let files_restored = extract_archive(path, &manifest, &staging)
+        .and_then(|_| replace_workspace(db, &manifest, &staging, &workspace, &previous_database));
+
+    let _ = fs::remove_dir_all(&staging);
+
+    let files_restored = files_restored?;
+    if let Err(e) = rotate_previous_databases() {
+        log::warn!("Failed to remove old copies of restored databases: {}", e);
+    }
+
+    Ok(RestoreReport {
+        files_restored,
+        previous_database: previous_database.display().to_string(),
+        manifest,
+    })
+}
+
+fn backup_archives() -> Result<Vec<PathBuf>, AppError> {
+    let entries = fs::read_dir(backup_dir()?)
+        .map_err(|e| AppError::with_cause("Failed to read backup directory", e))?;
+
+    let mut archives: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension() == Some(OsStr::new("zip")))
+        .collect();
+
+    // Names end in a unix timestamp, so this sorts oldest first within each prefix
+    archives.sort();
+    Ok(archives)
+}
+
+fn is_scheduled(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy().starts_with(SCHEDULED_PREFIX))
+        .unwrap_or(false)
+}
+
+pub fn list_backups() -> Result<Vec<BackupInfo>, AppError> {
+    let mut backups = Vec::new();
+
+    for path in backup_archives()? {
+        let manifest = match open_archive(&path).and_then(|mut archive| read_manifest(&mut archive)) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                log::warn!("Skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        backups.push(BackupInfo {
+            size: fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+            scheduled: is_scheduled(&path),
+            path: path.display().to_string(),
+            manifest,
+        });
+    }
+
+    backups.sort_by(|a, b| b.manifest.created_at.cmp(&a.manifest.created_at));
+    Ok(backups)
+}
+
+// Deletes the oldest scheduled backups so that at most `keep` remain.
+fn rotate_backups(keep: usize) -> Result<usize, AppError> {
+    let scheduled: Vec<PathBuf> = backup_archives()?.into_iter().filter(|path| is_scheduled(path)).collect();
+    let excess = scheduled.len().saturating_sub(keep.max(1));
+
+    for path in &scheduled[..excess] {
+        fs::remove_file(path)
+            .map_err(|e| AppError::with_cause(format!("Failed to delete old backup {}", path.display()), e))?;
+    }
+
+    Ok(excess)
+}
+
+pub fn get_backup_schedule(conn: &Connection) -> Result<BackupSchedule, AppError> {
+    Ok(get_setting(conn, SCHEDULE_KEY)?.unwrap_or_default())
+}
+
+fn last_scheduled_backup() -> Result<Option<SystemTime>, AppError> {
+    Ok(backup_archives()?
+        .into_iter()
+        .filter(|path| is_scheduled(path))
+        .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .max())
+}
+
+fn run_scheduled_backup(db: &Db) -> Result<(), AppError> {
+    // Waits for the passphrase of an encrypted database
+    if db.is_locked()? {
+        return Ok(());
+    }
+
+    let schedule = get_backup_schedule(&*db.conn()?)?;
+    if !schedule.enabled {
+        return Ok(());
+    }
+
+    let interval = Duration::from_secs(u64::from(schedule.interval_hours.max(1)) * 3600);
+    let due = match last_scheduled_backup()? {
+        Some(last) => last.elapsed().map(|elapsed| elapsed >= interval).unwrap_or(true),
+        None => true,
+    };
+    if !due {
+        return Ok(());
+    }
+
+    let backup = create_backup(db, &schedule.options, true)?;
+    log::info!("Created scheduled backup {}", backup.path);
+
+    let removed = rotate_backups(schedule.keep)?;
+    if removed > 0 {
+        log::info!("Removed {} old scheduled backups", removed);
+    }
+
+    Ok(())
+}
+
+// Checks periodically whether a scheduled backup is due. Called once from setup.
+pub fn start_backup_scheduler(app: AppHandle) {
+    std::thread::spawn(move || loop {
+        if let Err(e) = run_scheduled_backup(&app.state::<Db>()) {
+            log::warn!("Scheduled backup failed: {}", e);
+        }
+        std::thread::sleep(SCHEDULER_CHECK_INTERVAL);
+    });
+}
+
+#[tauri::command]
+pub async fn create_backup_command(app: AppHandle, options: Option<BackupOptions>) -> Result<BackupInfo, AppError> {
+    tokio::task::spawn_blocking(move || create_backup(&app.state::<Db>(), &options.unwrap_or_default(), false))
+        .await
+        .map_err(|e| AppError::with_cause("Backup failed", e))?
+}
+
+#[tauri::command]
+pub async fn validate_backup_command(path: String) -> Result<BackupManifest, AppError> {
+    tokio::task::spawn_blocking(move || validate_backup(Path::new(&path)))
+        .await
+        .map_err(|e| AppError::with_cause("Backup validation failed", e))?
+}
+
+#[tauri::command]
+pub async fn restore_backup_command(app: AppHandle, path: String) -> Result<RestoreReport, AppError> {
+    tokio::task::spawn_blocking(move || restore_backup(&app.state::<Db>(), Path::new(&path)))
+        .await
+        .map_err(|e| AppError::with_cause("Restore failed", e))?
+}
+
+#[tauri::command]
+pub fn list_backups_command() -> Result<Vec<BackupInfo>, AppError> {
+    list_backups()
+}
+
+#[tauri::command]
+pub fn get_backup_schedule_command(db: State<'_, Db>) -> Result<BackupSchedule, AppError> {
+    get_backup_schedule(&*db.conn()?)
+}
+
+#[tauri::command]
+pub fn set_backup_schedule_command(db: State<'_, Db>, schedule: BackupSchedule) -> Result<(), AppError> {
+    set_setting(&*db.conn()?, SCHEDULE_KEY, &schedule)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_only_workspace_data() {
+        for path in ["chats.db", "settings.json", "data/notes.txt", "attachments/ab/abcdef",
+                     "index/vectors.bin", "model/llama.gguf", "em_model/embed.gguf"] {
+            assert!(is_restorable(path), "{} should be restorable", path);
+        }
+
+        for path in ["setup/llama-cli", "setup/llama-cli.exe", "backups/old.zip", "chats.db-wal",
+                     "data/../setup/llama-cli", "./setup/llama-cli", "/etc/passwd", "../outside", ""] {
+            assert!(!is_restorable(path), "{} should be refused", path);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use rusqlite::{Connection, params};
+use tauri::State;
+use crate::db::db::{
+    attach_message_sources, current_timestamp, get_chat_messages, get_message, insert_message,
+    message_from_row, set_active_leaf, Db, Message, MessageRole, MESSAGE_SELECT,
+};
+use crate::error::AppError;
+
+// Other versions of a message: every message sharing its parent, oldest first.
+pub fn list_message_versions(conn: &Connection, message_id: i64) -> Result<Vec<Message>, AppError> {
+    let message = get_message(conn, message_id)?;
+
+    let mut stmt = conn.prepare_cached(
+        &format!("{} WHERE m.chat_id = ?1 AND m.parent_id IS ?2 ORDER BY m.id", MESSAGE_SELECT)
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let versions = stmt.query_map(params![message.chat_id, message.parent_id], message_from_row)
+        .map_err(|e| AppError::with_cause("Failed to query message versions", e))?;
+
+    let mut versions = versions.collect::<Result<Vec<Message>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect message versions", e))?;
+
+    attach_message_sources(conn, &mut versions)?;
+
+    Ok(versions)
+}
+
+// Follows the most recent reply at every level below `message_id`.
+fn latest_descendant(conn: &Connection, message_id: i64) -> Result<i64, AppError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT id FROM messages WHERE parent_id = ?1 ORDER BY id DESC LIMIT 1"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let mut leaf = message_id;
+    loop {
+        let child: Option<i64> = match stmt.query_row([leaf], |row| row.get(0)) {
+            Ok(child) => Some(child),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(AppError::with_cause("Failed to walk conversation branch", e)),
+        };
+
+        match child {
+            Some(child) => leaf = child,
+            None => return Ok(leaf),
+        }
+    }
+}
+
+// Makes the branch through `message_id` the active one and returns it.
+pub fn switch_branch(conn: &Connection, message_id: i64) -> Result<Vec<Message>, AppError> {
+    let message = get_message(conn, message_id)?;
+    let leaf = latest_descendant(conn, message_id)?;
+
+    set_active_leaf(conn, message.chat_id, leaf)?;
+
+    get_chat_messages(conn, message.chat_id)
+}
+
+// Saves an edited copy of a user message as a new sibling, which starts a new branch.
+pub fn edit_message(conn: &Connection, message_id: i64, content: String) -> Result<Message, AppError> {
+    let original = get_message(conn, message_id)?;
+
+    if !original.is_user {
+        return Err(AppError::invalid_input("Only user messages can be edited, regenerate assistant messages instead"));
+    }
+
+    let edited = Message {
+        chat_id: original.chat_id,
+        content,
+        is_user: true,
+        timestamp: current_timestamp(conn)?,
+        parent_id: original.parent_id,
+        role: Some(MessageRole::User),
+        ..Default::default()
+    };
+
+    let edited_id = insert_message(conn, original.chat_id, original.parent_id, &edited)?;
+    get_message(conn, edited_id)
+}
+
+// Saves a newly generated answer as a sibling of an assistant message.
+pub fn regenerate_message(conn: &Connection, message_id: i64, mut regenerated: Message) -> Result<Message, AppError> {
+    let original = get_message(conn, message_id)?;
+
+    if original.is_user {
+        return Err(AppError::invalid_input("Only assistant messages can be regenerated"));
+    }
+
+    regenerated.chat_id = original.chat_id;
+    regenerated.parent_id = original.parent_id;
+    regenerated.is_user = false;
+    if regenerated.role.is_none() {
+        regenerated.role = original.role;
+    }
+
+    let regenerated_id = insert_message(conn, original.chat_id, original.parent_id, &regenerated)?;
+    get_message(conn, regenerated_id)
+}
+
+#[tauri::command]
+pub fn edit_message_command(db: State<'_, Db>, message_id: i64, content: String) -> Result<Message, AppError> {
+    edit_message(&*db.conn()?, message_id, content)
+}
+
+#[tauri::command]
+pub fn regenerate_message_command(db: State<'_, Db>, message_id: i64, message: Message) -> Result<Message, AppError> {
+    regenerate_message(&*db.conn()?, message_id, message)
+}
+
+#[tauri::command]
+pub fn list_message_versions_command(db: State<'_, Db>, message_id: i64) -> Result<Vec<Message>, AppError> {
+    list_message_versions(&*db.conn()?, message_id)
+}
+
+#[tauri::command]
+pub fn switch_branch_command(db: State<'_, Db>, message_id: i64) -> Result<Vec<Message>, AppError> {
+    switch_branch(&*db.conn()?, message_id)
+}
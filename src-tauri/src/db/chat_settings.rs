@@ -0,0 +1,286 @@
+use rusqlite::{Connection, params};
+use serde::{Serialize, Deserialize};
+use tauri::{AppHandle, Manager, State};
+use crate::config::llama_cli;
+use crate::db::attachments::{get_attachment, get_attachment_text, retrieve_attachment_context, AttachmentMode};
+use crate::db::db::{Db, Message, MessageRole, MessageRouting, SamplingParams};
+use crate::db::history::get_chat_messages_page;
+use crate::db::settings::{get_setting, set_setting};
+use crate::rag::grounding::RetrievedChunk;
+use crate::rag::injection::{format_context, prepare_sources, SuspiciousChunkAction, UNTRUSTED_CONTEXT_NOTICE};
+use crate::rag::routing::{route_query, RetrievalMode};
+use crate::error::AppError;
+
+const DEFAULT_CHAT_SETTINGS_KEY: &str = "default_chat_settings";
+// Placeholders filled in by render_prompt
+pub const DEFAULT_PROMPT_TEMPLATE: &str = "{system_prompt}\n\n{history}User: {query}\nAssistant: ";
+pub const DEFAULT_RAG_TOP_N: usize = 3;
+// Earlier turns included in the prompt, older ones are left out
+const MAX_HISTORY_MESSAGES: u32 = 20;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RagOptions {
+    pub mode: RetrievalMode,
+    // Chunks retrieved per query, DEFAULT_RAG_TOP_N when unset
+    pub top_n: Option<usize>,
+    pub injection_action: SuspiciousChunkAction,
+}
+
+// Generation settings of one chat. New chats start with a copy of the defaults,
+// so changing the defaults later does not affect existing chats.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatSettings {
+    pub system_prompt: Option<String>,
+    // None uses the models selected with set_model and set_em_model
+    pub model: Option<String>,
+    pub embedding_model: Option<String>,
+    pub sampling: SamplingParams,
+    pub rag: RagOptions,
+    // Supports {system_prompt}, {history} and {query}, DEFAULT_PROMPT_TEMPLATE when unset
+    pub prompt_template: Option<String>,
+}
+
+// Everything the generation step needs for one turn of a chat
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PreparedPrompt {
+    pub prompt: String,
+    pub settings: ChatSettings,
+    // Attachment text pasted into the prompt, to be saved with the answer
+    pub sources: Vec<RetrievedChunk>,
+    // Whether the chat's indexed attachments were searched, to be saved with the answer
+    pub routing: MessageRouting,
+}
+
+pub fn get_default_chat_settings(conn: &Connection) -> Result<ChatSettings, AppError> {
+    Ok(get_setting(conn, DEFAULT_CHAT_SETTINGS_KEY)?.unwrap_or_default())
+}
+
+pub fn set_default_chat_settings(conn: &Connection, settings: &ChatSettings) -> Result<(), AppError> {
+    set_setting(conn, DEFAULT_CHAT_SETTINGS_KEY, settings)
+}
+
+// Settings stored for the chat, or the current defaults for chats created before
+// per-chat settings existed.
+pub fn get_chat_settings(conn: &Connection, chat_id: i64) -> Result<ChatSettings, AppError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT system_prompt, model, embedding_model, sampling_params, rag_options, prompt_template
+         FROM chat_settings WHERE chat_id = ?1"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let row = stmt.query_row([chat_id], |row| {
+        Ok((
+            row.get::<_, Option<String>>(0)?,
+            row.get::<_, Option<String>>(1)?,
+            row.get::<_, Option<String>>(2)?,
+            row.get::<_, Option<String>>(3)?,
+            row.get::<_, Option<String>>(4)?,
+            row.get::<_, Option<String>>(5)?,
+        ))
+    });
+
+    let (system_prompt, model, embedding_model, sampling, rag, prompt_template) = match row {
+        Ok(row) => row,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return get_default_chat_settings(conn),
+        Err(e) => return Err(AppError::with_cause("Failed to get chat settings", e)),
+    };
+
+    let sampling = sampling
+        .map(|sampling| serde_json::from_str(&sampling))
+        .transpose()
+        .map_err(|e| AppError::with_cause(format!("Invalid sampling parameters for chat {}", chat_id), e))?
+        .unwrap_or_default();
+
+    let rag = rag
+        .map(|rag| serde_json::from_str(&rag))
+        .transpose()
+        .map_err(|e| AppError::with_cause(format!("Invalid RAG options for chat {}", chat_id), e))?
+        .unwrap_or_default();
+
+    Ok(ChatSettings {
+        system_prompt,
+        model,
+        embedding_model,
+        sampling,
+        rag,
+        prompt_template,
+    })
+}
+
+pub fn set_chat_settings(conn: &Connection, chat_id: i64, settings: &ChatSettings) -> Result<(), AppError> {
+    let sampling = serde_json::to_string(&settings.sampling)
+        .map_err(|e| AppError::with_cause("Failed to serialize sampling parameters", e))?;
+    let rag = serde_json::to_string(&settings.rag)
+        .map_err(|e| AppError::with_cause("Failed to serialize RAG options", e))?;
+
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO chat_settings (
+            chat_id, system_prompt, model, embedding_model, sampling_params, rag_options, prompt_template
+         ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+         ON CONFLICT(chat_id) DO UPDATE SET
+            system_prompt = excluded.system_prompt,
+            model = excluded.model,
+            embedding_model = excluded.embedding_model,
+            sampling_params = excluded.sampling_params,
+            rag_options = excluded.rag_options,
+            prompt_template = excluded.prompt_template,
+            updated_at = CURRENT_TIMESTAMP"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    stmt.execute(params![
+        chat_id,
+        settings.system_prompt,
+        settings.model,
+        settings.embedding_model,
+        sampling,
+        rag,
+        settings.prompt_template,
+    ]).map_err(|e| AppError::with_cause("Failed to save chat settings", e))?;
+
+    Ok(())
+}
+
+// Gives a new chat its own copy of the default settings.
+pub fn inherit_default_settings(conn: &Connection, chat_id: i64) -> Result<(), AppError> {
+    let defaults = get_default_chat_settings(conn)?;
+    set_chat_settings(conn, chat_id, &defaults)
+}
+
+fn history_label(message: &Message) -> &'static str {
+    match message.role {
+        Some(MessageRole::System) => "System",
+        Some(MessageRole::Tool) => "Tool",
+        Some(MessageRole::User) => "User",
+        Some(MessageRole::Assistant) => "Assistant",
+        None if message.is_user => "User",
+        None => "Assistant",
+    }
+}
+
+pub fn render_prompt(settings: &ChatSettings, history: &[Message], query: &str) -> String {
+    let history: String = history.iter()
+        .map(|message| format!("{}: {}\n", history_label(message), message.content.trim()))
+        .collect();
+
+    let placeholders = [
+        ("{system_prompt}", settings.system_prompt.as_deref().unwrap_or("")),
+        ("{history}", history.as_str()),
+        ("{query}", query),
+    ];
+
+    // Single pass, so placeholders inside the values themselves are left alone
+    let mut prompt = String::new();
+    let mut rest = settings.prompt_template.as_deref().unwrap_or(DEFAULT_PROMPT_TEMPLATE);
+    while let Some(start) = rest.find('{') {
+        prompt.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        match placeholders.iter().find(|(placeholder, _)| rest.starts_with(placeholder)) {
+            Some((placeholder, value)) => {
+                prompt.push_str(value);
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                prompt.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    prompt.push_str(rest);
+
+    prompt.trim_start().to_string()
+}
+
+// Text of the inline attachments sent with this turn, followed by the chunks of the
+// chat's indexed attachments that match the query when `retrieve` is set.
+fn attachment_sources(conn: &Connection, chat_id: i64, query: &str, attachment_ids: &[i64], settings: &ChatSettings, retrieve: bool) -> Result<Vec<RetrievedChunk>, AppError> {
+    let mut contexts = Vec::new();
+
+    for attachment_id in attachment_ids {
+        let attachment = get_attachment(conn, *attachment_id)?;
+        if attachment.chat_id != chat_id {
+            return Err(AppError::invalid_input(format!("Attachment {} is not in chat {}", attachment_id, chat_id)));
+        }
+        if attachment.mode == AttachmentMode::Inline {
+            contexts.push((get_attachment_text(conn, attachment.id)?, attachment.filename));
+        }
+    }
+
+    if retrieve {
+        let top_n = settings.rag.top_n.unwrap_or(DEFAULT_RAG_TOP_N);
+        contexts.extend(retrieve_attachment_context(conn, chat_id, query, top_n as u32)?);
+    }
+
+    Ok(prepare_sources(contexts, settings.rag.injection_action))
+}
+
+// Builds the prompt for the next turn of a chat from its settings and recent history.
+// `query` is the user's message, or the RAG prompt built from it. Call it before the
+// user's message is saved, otherwise that message also appears in the history.
+// `attachment_ids` are the attachments sent with this turn and `routing` is the decision
+// of route_query for the message.
+pub fn prepare_chat_prompt(conn: &Connection, chat_id: i64, query: &str, attachment_ids: &[i64], routing: MessageRouting) -> Result<PreparedPrompt, AppError> {
+    let settings = get_chat_settings(conn, chat_id)?;
+    let history = get_chat_messages_page(conn, chat_id, None, None, MAX_HISTORY_MESSAGES)?.messages;
+    let sources = attachment_sources(conn, chat_id, query, attachment_ids, &settings, routing.retrieve)?;
+
+    let prompt = if sources.is_empty() {
+        render_prompt(&settings, &history, query)
+    } else {
+        let query = format!("{}\n\n{}\n\n{}", UNTRUSTED_CONTEXT_NOTICE, format_context(&sources), query);
+        render_prompt(&settings, &history, &query)
+    };
+
+    Ok(PreparedPrompt {
+        prompt,
+        settings,
+        sources,
+        routing,
+    })
+}
+
+#[tauri::command]
+pub fn get_chat_settings_command(db: State<'_, Db>, chat_id: i64) -> Result<ChatSettings, AppError> {
+    get_chat_settings(&*db.conn()?, chat_id)
+}
+
+#[tauri::command]
+pub fn set_chat_settings_command(db: State<'_, Db>, chat_id: i64, settings: ChatSettings) -> Result<(), AppError> {
+    set_chat_settings(&*db.conn()?, chat_id, &settings)
+}
+
+#[tauri::command]
+pub fn get_default_chat_settings_command(db: State<'_, Db>) -> Result<ChatSettings, AppError> {
+    get_default_chat_settings(&*db.conn()?)
+}
+
+#[tauri::command]
+pub fn set_default_chat_settings_command(db: State<'_, Db>, settings: ChatSettings) -> Result<(), AppError> {
+    set_default_chat_settings(&*db.conn()?, &settings)
+}
+
+// Routes the message with the chat's retrieval mode and model, then builds the prompt.
+#[tauri::command]
+pub async fn prepare_chat_prompt_command(
+    app: AppHandle,
+    chat_id: i64,
+    query: String,
+    attachment_ids: Option<Vec<i64>>,
+) -> Result<PreparedPrompt, AppError> {
+    tokio::task::spawn_blocking(move || {
+        let db = app.state::<Db>();
+        let settings = get_chat_settings(&*db.conn()?, chat_id)?;
+
+        // The classifier runs without holding the connection
+        let classifier = settings.model.map(|model| {
+            move |prompt: &str| llama_cli::complete(&model, prompt, 4)
+        });
+        let routed = route_query(&query, settings.rag.mode, classifier);
+
+        let conn = db.conn()?;
+        prepare_chat_prompt(&conn, chat_id, &routed.query, &attachment_ids.unwrap_or_default(), routed.routing)
+    })
+        .await
+        .map_err(|e| AppError::with_cause("Preparing the prompt failed", e))?
+}
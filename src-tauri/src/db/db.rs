@@ -1,13 +1,129 @@
-use rusqlite::{Connection, Result, params};
-use std::path::PathBuf;
-
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+use rusqlite::{Connection, Result, Row, params};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tauri::{AppHandle, State};
+use crate::db::attachments::{attach_message_attachments, remove_unused_attachment_files, Attachment};
+use crate::db::chat_settings::inherit_default_settings;
+use crate::db::encryption::{apply_key, is_encrypted};
+use crate::db::migrations::run_migrations;
+use crate::db::titles::spawn_title_generation;
+use crate::db::trash::purge_expired_trash;
+use crate::rag::grounding::RetrievedChunk;
+use crate::error::{AppError, ErrorCode};
+
+// How long a statement waits on a lock held by another connection before failing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+// Placeholder the UI gives new chats until they are titled
+const DEFAULT_CHAT_NAME: &str = "New Chat";
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
 pub struct Message {
     pub id: Option<i64>,
     pub chat_id: i64,
     pub content: String,
     pub is_user: bool,
     pub timestamp: String,
+    // Previous message in the conversation tree, defaults to the chat's active branch
+    #[serde(default)]
+    pub parent_id: Option<i64>,
+    // Derived from is_user when the client does not send it
+    #[serde(default)]
+    pub role: Option<MessageRole>,
+    // Model file that produced the message
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub sampling: Option<SamplingParams>,
+    #[serde(default)]
+    pub prompt_tokens: Option<i64>,
+    #[serde(default)]
+    pub completion_tokens: Option<i64>,
+    #[serde(default)]
+    pub time_to_first_token_ms: Option<i64>,
+    #[serde(default)]
+    pub latency_ms: Option<i64>,
+    // Chunks that were pasted into the prompt for this message
+    #[serde(default)]
+    pub sources: Vec<RetrievedChunk>,
+    #[serde(default)]
+    pub grounding: Option<MessageGrounding>,
+    #[serde(default)]
+    pub routing: Option<MessageRouting>,
+    // Set when the content was changed after the message was saved
+    #[serde(default)]
+    pub edited_at: Option<String>,
+    #[serde(default)]
+    pub star: Option<MessageStar>,
+    // Files sent with the message, see db::attachments
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageRole {
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+impl MessageRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageRole::System => "system",
+            MessageRole::User => "user",
+            MessageRole::Assistant => "assistant",
+            MessageRole::Tool => "tool",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "system" => Some(MessageRole::System),
+            "user" => Some(MessageRole::User),
+            "assistant" => Some(MessageRole::Assistant),
+            "tool" => Some(MessageRole::Tool),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SamplingParams {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub top_k: Option<u32>,
+    pub min_p: Option<f32>,
+    pub repeat_penalty: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub seed: Option<i64>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MessageGrounding {
+    pub score: f32,
+    pub unsupported_claims: Vec<String>,
+    pub checked_at: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MessageRouting {
+    pub retrieve: bool,
+    pub reason: String,
+    pub overridden: bool,
+    // Index similarity recorded by earlier versions, the router no longer sets it
+    #[serde(default)]
+    pub similarity: Option<f32>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MessageStar {
+    pub note: Option<String>,
+    pub starred_at: String,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -15,185 +131,732 @@ pub struct Chat {
     pub id: i64,
     pub name: String,
     pub created_at: String,
+    #[serde(default)]
+    pub folder_id: Option<i64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub archived: bool,
+    // Time of the latest message, or the creation time for an empty chat
+    #[serde(default)]
+    pub last_activity_at: Option<String>,
+    // Chat and message this chat was forked from, see fork_chat
+    #[serde(default)]
+    pub forked_from_chat_id: Option<i64>,
+    #[serde(default)]
+    pub forked_from_message_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatSort {
+    #[default]
+    LastActivity,
+    Name,
+    CreatedAt,
+}
+
+// Filters for get_all_chats_command. Unset filters match every chat;
+// pinned chats are always listed first.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ChatQuery {
+    pub folder_id: Option<i64>,
+    pub tag: Option<String>,
+    pub pinned: Option<bool>,
+    pub archived: Option<bool>,
+    // Forks of this chat
+    pub forked_from: Option<i64>,
+    pub sort: ChatSort,
+    // Defaults to newest first for dates and A to Z for names
+    pub ascending: Option<bool>,
 }
 
-pub fn get_db_path() -> Result<PathBuf, String> {
+pub fn get_db_path() -> Result<PathBuf, AppError> {
     let doc_dir = tauri::api::path::document_dir()
-        .ok_or_else(|| "Failed to get documents directory".to_string())?;
+        .ok_or_else(|| AppError::internal("Failed to get documents directory"))?;
     
     let workspace_path = doc_dir.join("LevChat");
     std::fs::create_dir_all(&workspace_path)
-        .map_err(|e| format!("Failed to create directory: {}", e))?;
+        .map_err(|e| AppError::with_cause("Failed to create directory", e))?;
     
     Ok(workspace_path.join("chats.db"))
 }
 
-pub fn initialize_database() -> Result<(), String> {
-    let db_path = get_db_path().map_err(|e| format!("Failed to get DB path: {}", e))?;
-    
-    let conn = Connection::open(&db_path)
-        .map_err(|e| format!("Failed to open database: {}", e))?;
-
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS chats (
-            id INTEGER PRIMARY KEY,
-            name TEXT NOT NULL,
-            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
-        )",
-        [],
-    ).map_err(|e| format!("Failed to create chats table: {}", e))?;
-
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS messages (
-            id INTEGER PRIMARY KEY,
-            chat_id INTEGER NOT NULL,
-            content TEXT NOT NULL,
-            is_user BOOLEAN NOT NULL,
-            timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
-            FOREIGN KEY(chat_id) REFERENCES chats(id)
-        )",
-        [],
-    ).map_err(|e| format!("Failed to create messages table: {}", e))?;
+// Single long-lived connection to chats.db, shared through Tauri state.
+// Access is serialized by the mutex; WAL and the busy timeout cover other processes.
+// An encrypted database stays locked, without a connection, until unlock is called
+// with its passphrase.
+pub struct Db {
+    path: PathBuf,
+    state: Mutex<DbState>,
+}
 
-    Ok(())
+#[derive(Default)]
+struct DbState {
+    conn: Option<Connection>,
+    // Kept in memory while unlocked so that snapshots and restores can use the same key
+    passphrase: Option<String>,
+}
+
+// Locked access to the open connection, returned by Db::conn
+pub struct DbConn<'a>(MutexGuard<'a, DbState>);
+
+impl Deref for DbConn<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.0.conn.as_ref().expect("DbConn is only created for an open connection")
+    }
+}
+
+impl DerefMut for DbConn<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.0.conn.as_mut().expect("DbConn is only created for an open connection")
+    }
 }
 
-pub fn create_new_chat(name: String) -> Result<i64, String> {
+impl DbConn<'_> {
+    pub fn passphrase(&self) -> Option<&str> {
+        self.0.passphrase.as_deref()
+    }
+}
 
-    initialize_database().map_err(|e| format!("Database initialization failed: {}", e))?;
+impl Db {
+    pub fn open() -> Result<Self, AppError> {
+        let db_path = get_db_path().map_err(|e| AppError::with_cause("Failed to get DB path", e))?;
+
+        let db = Db {
+            path: db_path,
+            state: Mutex::new(DbState::default()),
+        };
+
+        if is_encrypted(&db.path)? {
+            log::info!("Database is encrypted, waiting for the passphrase");
+            return Ok(db);
+        }
+
+        let conn = open_connection(&db.path, None)?;
+        db.lock_state()?.conn = Some(conn);
+
+        Ok(db)
+    }
+
+    // A command that panicked while holding the lock leaves the connection itself usable,
+    // and any transaction it had open is rolled back when it is dropped.
+    fn lock_state(&self) -> Result<MutexGuard<'_, DbState>, AppError> {
+        Ok(self.state.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    pub fn conn(&self) -> Result<DbConn<'_>, AppError> {
+        let state = self.lock_state()?;
+        if state.conn.is_none() {
+            return Err(AppError::new(ErrorCode::DatabaseLocked, "Database is locked, enter the passphrase to unlock it"));
+        }
+        Ok(DbConn(state))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_locked(&self) -> Result<bool, AppError> {
+        Ok(self.lock_state()?.conn.is_none())
+    }
+
+    pub fn is_encrypted(&self) -> Result<bool, AppError> {
+        let state = self.lock_state()?;
+        match state.conn {
+            Some(_) => Ok(state.passphrase.is_some()),
+            None => Ok(true),
+        }
+    }
+
+    // Opens an encrypted database. Does nothing when it is already unlocked.
+    pub fn unlock(&self, passphrase: &str) -> Result<(), AppError> {
+        let mut state = self.lock_state()?;
+        if state.conn.is_some() {
+            return Ok(());
+        }
+
+        state.conn = Some(open_connection(&self.path, Some(passphrase))?);
+        state.passphrase = Some(passphrase.to_string());
+
+        Ok(())
+    }
+
+    // Closes the connection, lets `change` replace or rekey the database file, then opens
+    // it again with `passphrase`. If `change` fails the previous key is used to reopen it.
+    pub(crate) fn reopen_with<F>(&self, passphrase: Option<String>, change: F) -> Result<(), AppError>
+    where
+        F: FnOnce(Connection, &Path) -> Result<(), AppError>,
+    {
+        let mut state = self.lock_state()?;
+        let conn = state.conn.take()
+            .ok_or_else(|| AppError::new(ErrorCode::DatabaseLocked, "Database is locked, enter the passphrase to unlock it"))?;
+
+        if let Err(e) = change(conn, &self.path) {
+            state.conn = Some(open_connection(&self.path, state.passphrase.as_deref())?);
+            return Err(e);
+        }
+
+        state.conn = Some(open_connection(&self.path, passphrase.as_deref())?);
+        state.passphrase = passphrase;
+
+        Ok(())
+    }
+}
 
-    let db_path = get_db_path().map_err(|e| format!("Failed to get DB path: {}", e))?;
+// Opens chats.db, applying the passphrase first when it is encrypted, and brings it up to date.
+fn open_connection(db_path: &Path, passphrase: Option<&str>) -> Result<Connection, AppError> {
     let conn = Connection::open(db_path)
-        .map_err(|e| format!("Failed to open database: {}", e))?;
+        .map_err(|e| AppError::with_cause("Failed to open database", e))?;
+
+    if let Some(passphrase) = passphrase {
+        apply_key(&conn, passphrase)?;
+    }
+
+    configure_connection(&conn)?;
+    run_migrations(&conn, db_path)?;
+
+    conn.pragma_update(None, "foreign_keys", true)
+        .map_err(|e| AppError::with_cause("Failed to enable foreign keys", e))?;
+
+    match purge_expired_trash(&conn) {
+        Ok(0) => {}
+        Ok(purged) => log::info!("Purged {} chats from the trash", purged),
+        Err(e) => log::warn!("Failed to purge trash: {}", e),
+    }
+
+    match remove_unused_attachment_files(&conn) {
+        Ok(0) => {}
+        Ok(removed) => log::info!("Removed {} unused attachment files", removed),
+        Err(e) => log::warn!("Failed to remove unused attachment files: {}", e),
+    }
+
+    Ok(conn)
+}
 
-    conn.execute(
-        "INSERT INTO chats (name) VALUES (?1)",
-        [&name],
-    ).map_err(|e| format!("Failed to insert chat: {}", e))?;
+fn configure_connection(conn: &Connection) -> Result<(), AppError> {
+    conn.busy_timeout(BUSY_TIMEOUT)
+        .map_err(|e| AppError::with_cause("Failed to set busy timeout", e))?;
+
+    conn.pragma_update(None, "journal_mode", "WAL")
+        .map_err(|e| AppError::with_cause("Failed to enable WAL journaling", e))?;
+
+    conn.pragma_update(None, "synchronous", "NORMAL")
+        .map_err(|e| AppError::with_cause("Failed to set synchronous mode", e))?;
+
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+
+    Ok(())
+}
+
+// Any name other than the placeholder was typed by the user and is kept by automatic titles.
+pub fn create_new_chat(conn: &Connection, name: String) -> Result<i64, AppError> {
+    let mut stmt = conn.prepare_cached("INSERT INTO chats (name, name_set_by_user) VALUES (?1, ?2)")
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    stmt.execute(params![name, name != DEFAULT_CHAT_NAME])
+        .map_err(|e| AppError::with_cause("Failed to insert chat", e))?;
 
     let chat_id = conn.last_insert_rowid();
+    inherit_default_settings(conn, chat_id)?;
+
     Ok(chat_id)
 }
 
-pub fn save_message(chat_id: i64, message: &Message) -> Result<(), String> {
-    let db_path = get_db_path().map_err(|e| format!("Failed to get DB path: {}", e))?;
-    let conn = Connection::open(db_path)
-        .map_err(|e| format!("Failed to open database: {}", e))?;
-
-    conn.execute(
-        "INSERT INTO messages (chat_id, content, is_user, timestamp) VALUES (?1, ?2, ?3, ?4)",
-        params![
-            chat_id, 
-            message.content, 
-            message.is_user, 
-            message.timestamp
-        ],
-    ).map_err(|e| format!("Failed to save message: {}", e))?;
+// Appends a message to the chat. Without an explicit parent it continues the active branch.
+pub fn save_message(conn: &Connection, chat_id: i64, message: &Message) -> Result<i64, AppError> {
+    let parent_id = match message.parent_id {
+        Some(parent_id) => Some(parent_id),
+        None => get_active_leaf(conn, chat_id)?,
+    };
+
+    insert_message(conn, chat_id, parent_id, message)
+}
 
+// Fails unless the chat exists and is not in the trash. Chats in the trash are read only
+// until they are restored.
+pub fn check_chat_writable(conn: &Connection, chat_id: i64) -> Result<(), AppError> {
+    let trashed: bool = conn.query_row("SELECT deleted_at IS NOT NULL FROM chats WHERE id = ?1", [chat_id], |row| row.get(0))
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::not_found(format!("Chat {} not found", chat_id)),
+            e => AppError::with_cause("Failed to get chat", e),
+        })?;
+
+    if trashed {
+        return Err(AppError::not_found(format!("Chat {} is in the trash", chat_id)));
+    }
     Ok(())
 }
 
-pub fn get_all_chats() -> Result<Vec<Chat>, String> {
-    let db_path = get_db_path().map_err(|e| format!("Failed to get DB path: {}", e))?;
-    let conn = Connection::open(db_path)
-        .map_err(|e| format!("Failed to open database: {}", e))?;
+// Fails unless `parent_id` is a message of `chat_id`, so branches never cross chats.
+fn check_parent(conn: &Connection, chat_id: i64, parent_id: i64) -> Result<(), AppError> {
+    let parent_chat_id: Option<i64> = conn.query_row("SELECT chat_id FROM messages WHERE id = ?1", [parent_id], |row| row.get(0))
+        .map(Some)
+        .or_else(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => Ok(None),
+            e => Err(AppError::with_cause("Failed to get parent message", e)),
+        })?;
+
+    if parent_chat_id != Some(chat_id) {
+        return Err(AppError::invalid_input(format!("Message {} is not in chat {}", parent_id, chat_id)));
+    }
+    Ok(())
+}
 
-    let mut stmt = conn.prepare("SELECT id, name, created_at FROM chats")
-        .map_err(|e| format!("Failed to prepare statement: {}", e))?;
-    
-    let chats = stmt.query_map([], |row| {
-        Ok(Chat {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            created_at: row.get(2)?,
-        })
-    }).map_err(|e| format!("Failed to query chats: {}", e))?;
+// Inserts a message under `parent_id` (None for a root) and makes it the chat's active leaf.
+// The message, its sources and its routing are saved together or not at all.
+pub fn insert_message(conn: &Connection, chat_id: i64, parent_id: Option<i64>, message: &Message) -> Result<i64, AppError> {
+    check_chat_writable(conn, chat_id)?;
+    if let Some(parent_id) = parent_id {
+        check_parent(conn, chat_id, parent_id)?;
+    }
+
+    // Callers that import or copy many messages already hold a transaction
+    let tx = if conn.is_autocommit() {
+        Some(conn.unchecked_transaction()
+            .map_err(|e| AppError::with_cause("Failed to start transaction", e))?)
+    } else {
+        None
+    };
+
+    let role = message.role.unwrap_or(if message.is_user {
+        MessageRole::User
+    } else {
+        MessageRole::Assistant
+    });
+
+    let sampling = message.sampling.as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| AppError::with_cause("Failed to serialize sampling parameters", e))?;
+
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO messages (
+            chat_id, content, is_user, timestamp, role, model, sampling_params,
+            prompt_tokens, completion_tokens, time_to_first_token_ms, latency_ms, parent_id
+         ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    stmt.execute(params![
+        chat_id, 
+        message.content, 
+        message.is_user, 
+        message.timestamp,
+        role.as_str(),
+        message.model,
+        sampling,
+        message.prompt_tokens,
+        message.completion_tokens,
+        message.time_to_first_token_ms,
+        message.latency_ms,
+        parent_id
+    ]).map_err(|e| AppError::with_cause("Failed to save message", e))?;
+
+    let message_id = conn.last_insert_rowid();
+
+    set_active_leaf(conn, chat_id, message_id)?;
+
+    if !message.sources.is_empty() {
+        let mut stmt = conn.prepare_cached(
+            "INSERT INTO message_sources (message_id, position, filename, text, flags)
+             VALUES (?1, ?2, ?3, ?4, ?5)"
+        ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+        for (position, source) in message.sources.iter().enumerate() {
+            let flags = serde_json::to_string(&source.flags)
+                .map_err(|e| AppError::with_cause("Failed to serialize source flags", e))?;
+
+            stmt.execute(params![message_id, position as i64, source.filename, source.text, flags])
+                .map_err(|e| AppError::with_cause("Failed to save message source", e))?;
+        }
+    }
+
+    if let Some(routing) = &message.routing {
+        let mut stmt = conn.prepare_cached(
+            "INSERT OR REPLACE INTO message_routing (message_id, retrieve, reason, overridden, similarity)
+             VALUES (?1, ?2, ?3, ?4, ?5)"
+        ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+        stmt.execute(params![
+            message_id,
+            routing.retrieve,
+            routing.reason,
+            routing.overridden,
+            routing.similarity
+        ]).map_err(|e| AppError::with_cause("Failed to save message routing", e))?;
+    }
+
+    if let Some(tx) = tx {
+        tx.commit()
+            .map_err(|e| AppError::with_cause("Failed to save message", e))?;
+    }
+
+    Ok(message_id)
+}
+
+pub fn save_message_grounding(conn: &Connection, message_id: i64, grounding: &MessageGrounding) -> Result<(), AppError> {
+    let unsupported_claims = serde_json::to_string(&grounding.unsupported_claims)
+        .map_err(|e| AppError::with_cause("Failed to serialize unsupported claims", e))?;
 
-    Ok(chats.collect::<Result<Vec<Chat>, _>>()
-        .map_err(|e| format!("Failed to collect chats: {}", e))?)
+    let mut stmt = conn.prepare_cached(
+        "INSERT OR REPLACE INTO message_grounding (message_id, score, unsupported_claims)
+         VALUES (?1, ?2, ?3)"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    stmt.execute(params![message_id, grounding.score, unsupported_claims])
+        .map_err(|e| AppError::with_cause("Failed to save message grounding", e))?;
+
+    Ok(())
 }
 
-pub fn get_chat_messages(chat_id: i64) -> Result<Vec<Message>, String> {
-    let db_path = get_db_path().map_err(|e| format!("Failed to get DB path: {}", e))?;
-    let conn = Connection::open(db_path)
-        .map_err(|e| format!("Failed to open database: {}", e))?;
+// Separates tags in the group_concat column of query_chats
+const TAG_SEPARATOR: char = '\u{1f}';
+
+pub fn query_chats(conn: &Connection, query: &ChatQuery) -> Result<Vec<Chat>, AppError> {
+    let (column, ascending_by_default) = match query.sort {
+        ChatSort::LastActivity => ("COALESCE(c.last_activity_at, c.created_at)", false),
+        ChatSort::Name => ("c.name COLLATE NOCASE", true),
+        ChatSort::CreatedAt => ("c.created_at", false),
+    };
+    let direction = if query.ascending.unwrap_or(ascending_by_default) { "ASC" } else { "DESC" };
+
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT c.id, c.name, c.created_at, c.folder_id, c.pinned, c.archived,
+                COALESCE(c.last_activity_at, c.created_at),
+                (SELECT group_concat(t.tag, char(31)) FROM chat_tags t WHERE t.chat_id = c.id),
+                c.forked_from_chat_id, c.forked_from_message_id
+         FROM chats c
+         WHERE c.deleted_at IS NULL
+           AND (?1 IS NULL OR c.folder_id = ?1)
+           AND (?2 IS NULL OR EXISTS (SELECT 1 FROM chat_tags t WHERE t.chat_id = c.id AND t.tag = ?2))
+           AND (?3 IS NULL OR c.pinned = ?3)
+           AND (?4 IS NULL OR c.archived = ?4)
+           AND (?5 IS NULL OR c.forked_from_chat_id = ?5)
+         ORDER BY c.pinned DESC, {} {}, c.id {}",
+        column, direction, direction
+    )).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let chats = stmt.query_map(
+        params![query.folder_id, query.tag, query.pinned, query.archived, query.forked_from],
+        |row| {
+            let tags: Option<String> = row.get(7)?;
+            Ok(Chat {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                created_at: row.get(2)?,
+                folder_id: row.get(3)?,
+                pinned: row.get(4)?,
+                archived: row.get(5)?,
+                last_activity_at: row.get(6)?,
+                tags: tags
+                    .map(|tags| tags.split(TAG_SEPARATOR).map(str::to_string).collect())
+                    .unwrap_or_default(),
+                forked_from_chat_id: row.get(8)?,
+                forked_from_message_id: row.get(9)?,
+            })
+        },
+    ).map_err(|e| AppError::with_cause("Failed to query chats", e))?;
+
+    chats.collect::<Result<Vec<Chat>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect chats", e))
+}
 
-    let mut stmt = conn.prepare(
-        "SELECT id, content, is_user, timestamp FROM messages 
-         WHERE chat_id = ?1 ORDER BY timestamp"
-    ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
-    
-    let messages = stmt.query_map([chat_id], |row| {
-        Ok(Message {
-            id: row.get(0)?,
-            chat_id,
-            content: row.get(1)?,
-            is_user: row.get(2)?,
-            timestamp: row.get(3)?,
-        })
-    }).map_err(|e| format!("Failed to query messages: {}", e))?;
-
-    Ok(messages.collect::<Result<Vec<Message>, _>>()
-        .map_err(|e| format!("Failed to collect messages: {}", e))?)
-}
-
-pub fn delete_chat(chat_id: i64) -> Result<(), String> {
-    let db_path = get_db_path().map_err(|e| format!("Failed to get DB path: {}", e))?;
-    let conn = Connection::open(db_path)
-        .map_err(|e| format!("Failed to open database: {}", e))?;
+// Every chat that is not in the trash, archived ones included
+pub fn get_all_chats(conn: &Connection) -> Result<Vec<Chat>, AppError> {
+    query_chats(conn, &ChatQuery::default())
+}
+
+// Columns read by message_from_row, joined with the grounding, routing and star side tables
+pub const MESSAGE_SELECT: &str =
+    "SELECT m.id, m.chat_id, m.content, m.is_user, m.timestamp,
+            m.role, m.model, m.sampling_params, m.prompt_tokens, m.completion_tokens,
+            m.time_to_first_token_ms, m.latency_ms,
+            g.score, g.unsupported_claims, g.checked_at,
+            r.retrieve, r.reason, r.overridden, r.similarity,
+            m.parent_id, m.edited_at, st.note, st.starred_at
+     FROM messages m
+     LEFT JOIN message_grounding g ON g.message_id = m.id
+     LEFT JOIN message_routing r ON r.message_id = m.id
+     LEFT JOIN message_stars st ON st.message_id = m.id";
+
+pub fn message_from_row(row: &Row) -> Result<Message> {
+    let is_user: bool = row.get(3)?;
+    let role: Option<String> = row.get(5)?;
+    let sampling: Option<String> = row.get(7)?;
+
+    let score: Option<f32> = row.get(12)?;
+    let unsupported_claims: Option<String> = row.get(13)?;
+    let checked_at: Option<String> = row.get(14)?;
+    let grounding = score.map(|score| MessageGrounding {
+        score,
+        unsupported_claims: unsupported_claims
+            .and_then(|claims| serde_json::from_str(&claims).ok())
+            .unwrap_or_default(),
+        checked_at,
+    });
+
+    let retrieve: Option<bool> = row.get(15)?;
+    let routing = match retrieve {
+        Some(retrieve) => Some(MessageRouting {
+            retrieve,
+            reason: row.get(16)?,
+            overridden: row.get(17)?,
+            similarity: row.get(18)?,
+        }),
+        None => None,
+    };
+
+    let starred_at: Option<String> = row.get(22)?;
+    let star = match starred_at {
+        Some(starred_at) => Some(MessageStar {
+            note: row.get(21)?,
+            starred_at,
+        }),
+        None => None,
+    };
+
+    Ok(Message {
+        id: row.get(0)?,
+        chat_id: row.get(1)?,
+        content: row.get(2)?,
+        is_user,
+        timestamp: row.get(4)?,
+        parent_id: row.get(19)?,
+        role: role.as_deref().and_then(MessageRole::parse),
+        model: row.get(6)?,
+        sampling: sampling.and_then(|sampling| serde_json::from_str(&sampling).ok()),
+        prompt_tokens: row.get(8)?,
+        completion_tokens: row.get(9)?,
+        time_to_first_token_ms: row.get(10)?,
+        latency_ms: row.get(11)?,
+        sources: Vec::new(),
+        grounding,
+        routing,
+        edited_at: row.get(20)?,
+        star,
+        attachments: Vec::new(),
+    })
+}
+
+// Fills in the retrieval sources and attachments of already loaded messages.
+pub fn attach_message_sources(conn: &Connection, messages: &mut [Message]) -> Result<(), AppError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT filename, text, flags FROM message_sources
+         WHERE message_id = ?1 ORDER BY position"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    for message in messages.iter_mut() {
+        let message_id = match message.id {
+            Some(id) => id,
+            None => continue,
+        };
+
+        message.sources = stmt.query_map([message_id], |row| {
+            let flags: Option<String> = row.get(2)?;
+            Ok(RetrievedChunk {
+                filename: row.get(0)?,
+                text: row.get(1)?,
+                flags: flags
+                    .and_then(|flags| serde_json::from_str(&flags).ok())
+                    .unwrap_or_default(),
+            })
+        }).map_err(|e| AppError::with_cause("Failed to query message sources", e))?
+        .collect::<Result<Vec<RetrievedChunk>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect message sources", e))?;
+    }
+
+    attach_message_attachments(conn, messages)
+}
 
-    conn.execute(
-        "DELETE FROM messages WHERE chat_id = ?1",
-        [chat_id],
-    ).map_err(|e| format!("Failed to delete chat messages: {}", e))?;
+pub fn get_message(conn: &Connection, message_id: i64) -> Result<Message, AppError> {
+    let mut stmt = conn.prepare_cached(&format!("{} WHERE m.id = ?1", MESSAGE_SELECT))
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let mut message = stmt.query_row([message_id], message_from_row)
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::not_found(format!("Message {} not found", message_id)),
+            e => AppError::with_cause("Failed to get message", e),
+        })?;
+
+    attach_message_sources(conn, std::slice::from_mut(&mut message))?;
+
+    Ok(message)
+}
+
+// Current time in the same ISO 8601 format the frontend uses for message timestamps
+pub fn current_timestamp(conn: &Connection) -> Result<String, AppError> {
+    conn.query_row("SELECT strftime('%Y-%m-%dT%H:%M:%fZ', 'now')", [], |row| row.get(0))
+        .map_err(|e| AppError::with_cause("Failed to get current time", e))
+}
 
-    conn.execute(
-        "DELETE FROM chats WHERE id = ?1",
-        [chat_id],
-    ).map_err(|e| format!("Failed to delete chat: {}", e))?;
+pub fn get_active_leaf(conn: &Connection, chat_id: i64) -> Result<Option<i64>, AppError> {
+    let mut stmt = conn.prepare_cached("SELECT active_leaf_id FROM chats WHERE id = ?1")
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    stmt.query_row([chat_id], |row| row.get(0))
+        .map_err(|e| AppError::with_cause("Failed to get active branch", e))
+}
+
+pub fn set_active_leaf(conn: &Connection, chat_id: i64, message_id: i64) -> Result<(), AppError> {
+    let mut stmt = conn.prepare_cached("UPDATE chats SET active_leaf_id = ?1 WHERE id = ?2")
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    stmt.execute(params![message_id, chat_id])
+        .map_err(|e| AppError::with_cause("Failed to set active branch", e))?;
 
     Ok(())
 }
-pub fn rename_chat(chat_id: i64, new_name: String) -> Result<(), String> {
-    let db_path = get_db_path()?;
-    let conn = Connection::open(db_path)
-        .map_err(|e| format!("Failed to open database: {}", e))?;
 
-    conn.execute(
-        "UPDATE chats SET name = ?1 WHERE id = ?2",
-        params![new_name, chat_id]
-    ).map_err(|e| format!("Failed to rename chat: {}", e))?;
+// Returns the messages on the chat's active branch, from the root to the active leaf.
+pub fn get_chat_messages(conn: &Connection, chat_id: i64) -> Result<Vec<Message>, AppError> {
+    let mut stmt = conn.prepare_cached(
+        &format!(
+            "WITH RECURSIVE branch(id, depth) AS (
+                SELECT active_leaf_id, 0 FROM chats WHERE id = ?1 AND active_leaf_id IS NOT NULL
+                UNION ALL
+                SELECT p.parent_id, branch.depth + 1 FROM messages p
+                JOIN branch ON p.id = branch.id
+                WHERE p.parent_id IS NOT NULL
+            )
+            {}
+            JOIN branch b ON b.id = m.id
+            ORDER BY b.depth DESC",
+            MESSAGE_SELECT
+        )
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+    
+    let messages = stmt.query_map([chat_id], message_from_row)
+        .map_err(|e| AppError::with_cause("Failed to query messages", e))?;
+
+    let mut messages = messages.collect::<Result<Vec<Message>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect messages", e))?;
+
+    attach_message_sources(conn, &mut messages)?;
+
+    Ok(messages)
+}
+
+// Moves a chat to the trash. It stays restorable until it is purged, see db::trash.
+pub fn delete_chat(conn: &Connection, chat_id: i64) -> Result<(), AppError> {
+    let mut stmt = conn.prepare_cached(
+        "UPDATE chats SET deleted_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+         WHERE id = ?1 AND deleted_at IS NULL"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let deleted = stmt.execute([chat_id])
+        .map_err(|e| AppError::with_cause("Failed to delete chat", e))?;
+
+    if deleted == 0 {
+        return Err(AppError::not_found(format!("Chat {} not found", chat_id)));
+    }
 
     Ok(())
 }
 
+// Renames a chat. Generated titles (`by_user` false) never replace a name the user chose.
+pub fn rename_chat(conn: &Connection, chat_id: i64, new_name: String, by_user: bool) -> Result<bool, AppError> {
+    check_chat_writable(conn, chat_id)?;
+
+    let mut stmt = conn.prepare_cached(
+        "UPDATE chats SET name = ?1, name_set_by_user = name_set_by_user OR ?3
+         WHERE id = ?2 AND (?3 OR NOT name_set_by_user)"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let renamed = stmt.execute(params![new_name, chat_id, by_user])
+        .map_err(|e| AppError::with_cause("Failed to rename chat", e))?;
+
+    Ok(renamed > 0)
+}
+
 #[tauri::command]
-pub fn rename_chat_command(chat_id: i64, new_name: String) -> Result<(), String> {
-    rename_chat(chat_id, new_name)
+pub fn rename_chat_command(db: State<'_, Db>, chat_id: i64, new_name: String) -> Result<(), AppError> {
+    rename_chat(&*db.conn()?, chat_id, new_name, true)?;
+    Ok(())
 }
 
 
 #[tauri::command]
-pub fn delete_chat_command(chat_id: i64) -> Result<(), String> {
-    delete_chat(chat_id)
+pub fn delete_chat_command(db: State<'_, Db>, chat_id: i64) -> Result<(), AppError> {
+    delete_chat(&*db.conn()?, chat_id)
 }
 
 #[tauri::command]
-pub fn create_new_chat_command(name: String) -> Result<i64, String> {
-    create_new_chat(name)
+pub fn create_new_chat_command(db: State<'_, Db>, name: String) -> Result<i64, AppError> {
+    create_new_chat(&*db.conn()?, name)
 }
 
 #[tauri::command]
-pub fn save_message_command(chat_id: i64, message: Message) -> Result<(), String> {
-    save_message(chat_id, &message)
+pub fn save_message_command(app: AppHandle, db: State<'_, Db>, chat_id: i64, message: Message) -> Result<i64, AppError> {
+    let conn = db.conn()?;
+    let message_id = save_message(&conn, chat_id, &message)?;
+
+    if let Err(e) = spawn_title_generation(&app, &conn, chat_id, &message) {
+        log::warn!("Failed to start title generation for chat {}: {}", chat_id, e);
+    }
+
+    Ok(message_id)
 }
 
 #[tauri::command]
-pub fn get_chat_messages_command(chat_id: i64) -> Result<Vec<Message>, String> {
-    get_chat_messages(chat_id)
+pub fn get_chat_messages_command(db: State<'_, Db>, chat_id: i64) -> Result<Vec<Message>, AppError> {
+    get_chat_messages(&*db.conn()?, chat_id)
 }
 
 #[tauri::command]
-pub fn get_all_chats_command() -> Result<Vec<Chat>, String> {
-    get_all_chats()
+pub fn get_all_chats_command(db: State<'_, Db>, query: Option<ChatQuery>) -> Result<Vec<Chat>, AppError> {
+    query_chats(&*db.conn()?, &query.unwrap_or_default())
+}
+
+// Shared by the tests of the db modules
+#[cfg(test)]
+pub(crate) mod test_support {
+    use super::*;
+
+    // An up to date database in memory, with foreign keys on like open_connection
+    pub fn memory_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn, Path::new(":memory:")).unwrap();
+        conn.pragma_update(None, "foreign_keys", true).unwrap();
+        conn
+    }
+
+    pub fn message(content: &str, is_user: bool) -> Message {
+        Message {
+            id: None,
+            chat_id: 0,
+            content: content.to_string(),
+            is_user,
+            timestamp: String::new(),
+            parent_id: None,
+            role: None,
+            model: None,
+            sampling: None,
+            prompt_tokens: None,
+            completion_tokens: None,
+            time_to_first_token_ms: None,
+            latency_ms: None,
+            sources: Vec::new(),
+            grounding: None,
+            routing: None,
+            edited_at: None,
+            star: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    // A chat with one message per entry of `contents`, alternating user and assistant,
+    // each answering the previous one. Returns the chat and the message ids in order.
+    pub fn chat_with(conn: &Connection, contents: &[&str]) -> (i64, Vec<i64>) {
+        let chat_id = create_new_chat(conn, "Test chat".to_string()).unwrap();
+        let ids = contents.iter()
+            .enumerate()
+            .map(|(i, content)| save_message(conn, chat_id, &message(content, i % 2 == 0)).unwrap())
+            .collect();
+        (chat_id, ids)
+    }
 }
@@ -0,0 +1,209 @@
+use rusqlite::{Connection, DatabaseName};
+use serde::{Serialize, Deserialize};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use tauri::{AppHandle, Manager, State};
+use crate::db::attachments::ATTACHMENTS_DIR;
+use crate::db::db::Db;
+use crate::db::migrations::schema_version;
+use crate::error::{AppError, ErrorCode};
+
+// First bytes of every unencrypted SQLite file. SQLCipher files start with a random salt instead.
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+const ENCRYPTING_SUFFIX: &str = "encrypting";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DatabaseStatus {
+    pub encrypted: bool,
+    // Encrypted and still waiting for the passphrase
+    pub locked: bool,
+}
+
+// Sets the key of a freshly opened connection and checks that it can read the database.
+// Must run before any other statement on the connection.
+pub fn apply_key(conn: &Connection, passphrase: &str) -> Result<(), AppError> {
+    conn.pragma_update(None, "key", passphrase)
+        .map_err(|e| AppError::with_cause("Failed to set database key", e))?;
+
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0))
+        .map_err(|e| match e.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::NotADatabase) => AppError::new(ErrorCode::WrongPassphrase, "Wrong passphrase"),
+            _ => AppError::with_cause("Failed to read encrypted database", e),
+        })?;
+
+    Ok(())
+}
+
+// Whether the file at `path` is a SQLCipher database. Missing and empty files are not.
+pub fn is_encrypted(path: &Path) -> Result<bool, AppError> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(AppError::with_cause(format!("Failed to open {}", path.display()), e)),
+    };
+
+    let mut header = Vec::with_capacity(SQLITE_HEADER.len());
+    file.by_ref().take(SQLITE_HEADER.len() as u64).read_to_end(&mut header)
+        .map_err(|e| AppError::with_cause(format!("Failed to read {}", path.display()), e))?;
+
+    Ok(!header.is_empty() && header != SQLITE_HEADER)
+}
+
+fn check_passphrase(passphrase: &str) -> Result<(), AppError> {
+    if passphrase.is_empty() {
+        return Err(AppError::invalid_input("Passphrase cannot be empty"));
+    }
+    Ok(())
+}
+
+// Removes the WAL and shared memory files left next to a closed database.
+fn remove_sidecar_files(db_path: &Path) -> Result<(), AppError> {
+    for suffix in ["-wal", "-shm"] {
+        let mut sidecar = db_path.as_os_str().to_owned();
+        sidecar.push(suffix);
+        match fs::remove_file(&sidecar) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(AppError::with_cause(format!("Failed to remove {}", Path::new(&sidecar).display()), e)),
+        }
+    }
+    Ok(())
+}
+
+fn write_encrypted_copy(conn: &Connection, encrypted_path: &Path, passphrase: &str) -> Result<(), AppError> {
+    let version = schema_version(conn)?;
+
+    conn.execute("ATTACH DATABASE ?1 AS encrypted KEY ?2", [&*encrypted_path.to_string_lossy(), passphrase])
+        .map_err(|e| AppError::with_cause("Failed to create encrypted database", e))?;
+
+    conn.query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))
+        .map_err(|e| AppError::with_cause("Failed to encrypt database", e))?;
+
+    // sqlcipher_export copies the schema and rows but not the user_version used for migrations
+    conn.pragma_update(Some(DatabaseName::Attached("encrypted")), "user_version", version)
+        .map_err(|e| AppError::with_cause("Failed to set schema version", e))?;
+
+    conn.execute("DETACH DATABASE encrypted", [])
+        .map_err(|e| AppError::with_cause("Failed to finish encrypted database", e))?;
+
+    Ok(())
+}
+
+// Writes an encrypted copy of the database next to it, then replaces the original.
+fn export_encrypted(conn: Connection, db_path: &Path, passphrase: &str) -> Result<(), AppError> {
+    let encrypted_path = db_path.with_extension(format!("db.{}", ENCRYPTING_SUFFIX));
+    let _ = fs::remove_file(&encrypted_path);
+
+    if let Err(e) = write_encrypted_copy(&conn, &encrypted_path, passphrase) {
+        let _ = fs::remove_file(&encrypted_path);
+        return Err(e);
+    }
+
+    // Closing the last connection checkpoints the WAL, so nothing is left in it
+    conn.close()
+        .map_err(|(_, e)| AppError::with_cause("Failed to close database", e))?;
+    remove_sidecar_files(db_path)?;
+
+    fs::rename(&encrypted_path, db_path)
+        .map_err(|e| AppError::with_cause("Failed to replace database with the encrypted copy", e))
+}
+
+// Unencrypted copies of the database left by migrations, restores and backups, and the
+// attachments folder when it holds files, which are never encrypted.
+fn plaintext_copies(db_path: &Path) -> Vec<String> {
+    let workspace = match db_path.parent() {
+        Some(workspace) => workspace,
+        None => return Vec::new(),
+    };
+
+    let attachments = workspace.join(ATTACHMENTS_DIR);
+    let has_attachments = fs::read_dir(&attachments)
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false);
+
+    let entries = match fs::read_dir(workspace.join("backups")) {
+        Ok(entries) => entries,
+        Err(_) if has_attachments => return vec![attachments.display().to_string()],
+        Err(_) => return Vec::new(),
+    };
+
+    let mut copies: Vec<String> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| match path.extension().and_then(|ext| ext.to_str()) {
+            Some("db") => matches!(is_encrypted(path), Ok(false)),
+            // Every archive made so far holds the unencrypted database
+            Some("zip") => true,
+            _ => false,
+        })
+        .map(|path| path.display().to_string())
+        .collect();
+
+    copies.sort();
+    if has_attachments {
+        copies.push(attachments.display().to_string());
+    }
+    copies
+}
+
+pub fn get_database_status(db: &Db) -> Result<DatabaseStatus, AppError> {
+    Ok(DatabaseStatus {
+        encrypted: db.is_encrypted()?,
+        locked: db.is_locked()?,
+    })
+}
+
+// Migrates an unencrypted database to SQLCipher. Returns the unencrypted copies found in
+// the backups folder, which the user may want to delete.
+pub fn encrypt_database(db: &Db, passphrase: &str) -> Result<Vec<String>, AppError> {
+    check_passphrase(passphrase)?;
+    if db.is_encrypted()? {
+        return Err(AppError::invalid_input("Database is already encrypted"));
+    }
+
+    db.reopen_with(Some(passphrase.to_string()), |conn, db_path| {
+        export_encrypted(conn, db_path, passphrase)
+    })?;
+
+    log::info!("Encrypted the chat database");
+    Ok(plaintext_copies(db.path()))
+}
+
+pub fn change_passphrase(db: &Db, current: &str, new: &str) -> Result<(), AppError> {
+    check_passphrase(new)?;
+    if db.conn()?.passphrase() != Some(current) {
+        return Err(AppError::new(ErrorCode::WrongPassphrase, "Current passphrase is wrong or the database is not encrypted"));
+    }
+
+    db.reopen_with(Some(new.to_string()), |conn, _| {
+        conn.pragma_update(None, "rekey", new)
+            .map_err(|e| AppError::with_cause("Failed to change passphrase", e))
+    })?;
+
+    log::info!("Changed the database passphrase");
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_database_status_command(db: State<'_, Db>) -> Result<DatabaseStatus, AppError> {
+    get_database_status(&db)
+}
+
+#[tauri::command]
+pub fn unlock_database_command(db: State<'_, Db>, passphrase: String) -> Result<(), AppError> {
+    db.unlock(&passphrase)
+}
+
+#[tauri::command]
+pub async fn encrypt_database_command(app: AppHandle, passphrase: String) -> Result<Vec<String>, AppError> {
+    tokio::task::spawn_blocking(move || encrypt_database(&app.state::<Db>(), &passphrase))
+        .await
+        .map_err(|e| AppError::with_cause("Encryption failed", e))?
+}
+
+#[tauri::command]
+pub async fn change_database_passphrase_command(app: AppHandle, current: String, new: String) -> Result<(), AppError> {
+    tokio::task::spawn_blocking(move || change_passphrase(&app.state::<Db>(), &current, &new))
+        .await
+        .map_err(|e| AppError::with_cause("Passphrase change failed", e))?
+}
@@ -0,0 +1,379 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use rusqlite::Connection;
+use serde::{Serialize, Deserialize};
+use std::path::PathBuf;
+use tauri::State;
+use crate::db::db::{current_timestamp, get_all_chats, get_chat_messages, Chat, Db, Message, MessageRole};
+use crate::error::AppError;
+
+// LevChat JSON export format
+//
+// {
+//   "format": "levchat-export",
+//   "version": 1,
+//   "exported_at": "2024-05-01T10:00:00.000Z",
+//   "chats": [
+//     {
+//       "id": 1,
+//       "name": "Chat name",
+//       "created_at": "2024-05-01 09:00:00",
+//       "messages": [ Message, ... ]
+//     }
+//   ]
+// }
+//
+// Each message has the same shape as returned by get_chat_messages_command:
+// id, chat_id, parent_id, content, is_user, role ("system" | "user" | "assistant" | "tool"),
+// timestamp, model, sampling, prompt_tokens, completion_tokens, time_to_first_token_ms,
+// latency_ms, sources ([{ filename, text, flags }]), grounding, routing, edited_at and
+// star ({ note, starred_at }).
+// Only the active branch of each chat is exported, in conversation order.
+// Fields that were not recorded are null; readers must ignore unknown fields.
+pub const EXPORT_FORMAT: &str = "levchat-export";
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Html,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatExport {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: String,
+    pub chats: Vec<ExportedChat>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedChat {
+    pub id: i64,
+    pub name: String,
+    pub created_at: String,
+    pub messages: Vec<Message>,
+}
+
+lazy_static! {
+    static ref CODE_TOKEN: Regex = Regex::new(concat!(
+        r#"(?P<comment>//[^\n]*|#[^\n]*|/\*[\s\S]*?\*/|--[^\n]*)"#,
+        r#"|(?P<string>"(?:\\.|[^"\\])*"|'(?:\\.|[^'\\])*'|`(?:\\.|[^`\\])*`)"#,
+        r#"|(?P<number>\b\d+(?:\.\d+)?\b)"#,
+        r#"|(?P<keyword>\b(?:fn|let|mut|pub|struct|enum|impl|trait|use|mod|match|if|else|for|while|loop|return|break|continue|async|await|def|class|import|from|as|in|is|not|and|or|None|True|False|self|Self|function|const|var|new|this|null|undefined|true|false|public|private|static|void|int|float|double|bool|string|try|catch|except|finally|raise|throw|with|yield|lambda|select|insert|update|delete|where|join|create|table)\b)"#,
+    )).unwrap();
+    static ref INLINE_CODE: Regex = Regex::new(r"`([^`\n]+)`").unwrap();
+}
+
+fn role_label(message: &Message) -> &'static str {
+    match message.role {
+        Some(MessageRole::System) => "System",
+        Some(MessageRole::User) => "User",
+        Some(MessageRole::Assistant) => "Assistant",
+        Some(MessageRole::Tool) => "Tool",
+        None if message.is_user => "User",
+        None => "Assistant",
+    }
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn source_preview(text: &str) -> String {
+    let preview: String = text.chars().take(200).collect();
+    if preview.len() < text.len() {
+        format!("{}…", preview.trim_end())
+    } else {
+        preview
+    }
+}
+
+pub fn collect_chats(conn: &Connection, chat_id: Option<i64>) -> Result<Vec<ExportedChat>, AppError> {
+    let chats: Vec<Chat> = get_all_chats(conn)?
+        .into_iter()
+        .filter(|chat| chat_id.is_none() || chat_id == Some(chat.id))
+        .collect();
+
+    if let (Some(id), true) = (chat_id, chats.is_empty()) {
+        return Err(AppError::not_found(format!("Chat {} not found", id)));
+    }
+
+    chats.into_iter()
+        .map(|chat| {
+            let messages = get_chat_messages(conn, chat.id)?;
+            Ok(ExportedChat {
+                id: chat.id,
+                name: chat.name,
+                created_at: chat.created_at,
+                messages,
+            })
+        })
+        .collect()
+}
+
+pub fn render_json(chats: Vec<ExportedChat>, exported_at: String) -> Result<String, AppError> {
+    let export = ChatExport {
+        format: EXPORT_FORMAT.to_string(),
+        version: EXPORT_FORMAT_VERSION,
+        exported_at,
+        chats,
+    };
+
+    serde_json::to_string_pretty(&export)
+        .map_err(|e| AppError::with_cause("Failed to serialize export", e))
+}
+
+pub fn render_markdown(chats: &[ExportedChat], exported_at: &str) -> String {
+    let mut out = String::new();
+
+    for chat in chats {
+        out.push_str(&format!("# {}\n\n", chat.name));
+        out.push_str(&format!("_Created {} · exported from LevChat {}_\n\n", chat.created_at, exported_at));
+
+        for message in &chat.messages {
+            let model = message.model.as_deref()
+                .map(|model| format!(" ({})", model))
+                .unwrap_or_default();
+            out.push_str(&format!("### {}{} · {}\n\n", role_label(message), model, message.timestamp));
+            out.push_str(message.content.trim());
+            out.push_str("\n\n");
+
+            if !message.sources.is_empty() {
+                out.push_str("**Sources**\n\n");
+                for source in &message.sources {
+                    out.push_str(&format!("- *{}*: {}\n", source.filename, source_preview(&source.text).replace('\n', " ")));
+                }
+                out.push('\n');
+            }
+        }
+
+        out.push_str("---\n\n");
+    }
+
+    out
+}
+
+fn highlight_code(code: &str) -> String {
+    let mut out = String::new();
+    let mut last = 0;
+
+    for caps in CODE_TOKEN.captures_iter(code) {
+        let token = caps.get(0).unwrap();
+        out.push_str(&escape_html(&code[last..token.start()]));
+
+        let class = ["comment", "string", "number", "keyword"]
+            .iter()
+            .find(|name| caps.name(name).is_some())
+            .copied()
+            .unwrap_or("plain");
+
+        out.push_str(&format!("<span class=\"tok-{}\">{}</span>", class, escape_html(token.as_str())));
+        last = token.end();
+    }
+
+    out.push_str(&escape_html(&code[last..]));
+    out
+}
+
+fn render_text_html(text: &str) -> String {
+    text.split("\n\n")
+        .filter(|paragraph| !paragraph.trim().is_empty())
+        .map(|paragraph| {
+            let escaped = escape_html(paragraph.trim());
+            let with_code = INLINE_CODE.replace_all(&escaped, "<code>$1</code>");
+            format!("<p>{}</p>", with_code.replace('\n', "<br>"))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Renders message content, turning fenced ``` blocks into highlighted <pre> blocks.
+fn render_content_html(content: &str) -> String {
+    let mut out = String::new();
+
+    for (i, part) in content.split("```").enumerate() {
+        if i % 2 == 0 {
+            out.push_str(&render_text_html(part));
+            continue;
+        }
+
+        let (language, code) = match part.split_once('\n') {
+            Some((language, code)) => (language.trim(), code),
+            None => ("", part),
+        };
+        out.push_str(&format!(
+            "<pre><code class=\"language-{}\">{}</code></pre>",
+            escape_html(language),
+            highlight_code(code.trim_end())
+        ));
+    }
+
+    out
+}
+
+const HTML_STYLE: &str = r#"
+body { font-family: -apple-system, BlinkMacSystemFont, "Segoe UI", sans-serif; max-width: 860px; margin: 2rem auto; padding: 0 1rem; color: #1f2328; background: #fff; }
+h1 { border-bottom: 1px solid #d0d7de; padding-bottom: .3rem; }
+.meta { color: #656d76; font-size: .85rem; }
+.message { border: 1px solid #d0d7de; border-radius: 8px; padding: .75rem 1rem; margin: 1rem 0; }
+.message.user { background: #f6f8fa; }
+.message header { font-weight: 600; margin-bottom: .5rem; }
+.message header .meta { font-weight: normal; margin-left: .5rem; }
+pre { background: #0d1117; color: #e6edf3; padding: .75rem; border-radius: 6px; overflow-x: auto; }
+code { font-family: ui-monospace, SFMono-Regular, Menlo, monospace; font-size: .85rem; }
+p code { background: #eff1f3; padding: .1rem .3rem; border-radius: 4px; }
+.tok-comment { color: #8b949e; font-style: italic; }
+.tok-string { color: #a5d6ff; }
+.tok-number { color: #79c0ff; }
+.tok-keyword { color: #ff7b72; }
+.sources { font-size: .85rem; color: #656d76; }
+.sources li { margin: .25rem 0; }
+"#;
+
+pub fn render_html(chats: &[ExportedChat], exported_at: &str) -> String {
+    let mut body = String::new();
+
+    for chat in chats {
+        body.push_str(&format!(
+            "<section class=\"chat\">\n<h1>{}</h1>\n<p class=\"meta\">Created {}</p>\n",
+            escape_html(&chat.name),
+            escape_html(&chat.created_at)
+        ));
+
+        for message in &chat.messages {
+            let role = role_label(message);
+            let mut meta = vec![escape_html(&message.timestamp)];
+            if let Some(model) = &message.model {
+                meta.push(escape_html(model));
+            }
+            if let Some(latency) = message.latency_ms {
+                meta.push(format!("{} ms", latency));
+            }
+
+            body.push_str(&format!(
+                "<article class=\"message {}\">\n<header>{}<span class=\"meta\">{}</span></header>\n{}\n",
+                role.to_lowercase(),
+                role,
+                meta.join(" · "),
+                render_content_html(&message.content)
+            ));
+
+            if !message.sources.is_empty() {
+                body.push_str("<details class=\"sources\"><summary>Sources</summary><ul>\n");
+                for source in &message.sources {
+                    body.push_str(&format!(
+                        "<li><strong>{}</strong>: {}</li>\n",
+                        escape_html(&source.filename),
+                        escape_html(&source_preview(&source.text))
+                    ));
+                }
+                body.push_str("</ul></details>\n");
+            }
+
+            body.push_str("</article>\n");
+        }
+
+        body.push_str("</section>\n");
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>LevChat export</title>\n<style>{}</style>\n</head>\n<body>\n<p class=\"meta\">Exported from LevChat {}</p>\n{}</body>\n</html>\n",
+        HTML_STYLE,
+        escape_html(exported_at),
+        body
+    )
+}
+
+// Exports one chat (or every chat when `chat_id` is None) and writes it to `path`.
+pub fn export_chats(conn: &Connection, chat_id: Option<i64>, format: ExportFormat, path: &str) -> Result<String, AppError> {
+    let chats = collect_chats(conn, chat_id)?;
+    let exported_at = current_timestamp(conn)?;
+
+    let contents = match format {
+        ExportFormat::Json => render_json(chats, exported_at)?,
+        ExportFormat::Markdown => render_markdown(&chats, &exported_at),
+        ExportFormat::Html => render_html(&chats, &exported_at),
+    };
+
+    let path = PathBuf::from(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| AppError::with_cause("Failed to create directory", e))?;
+    }
+
+    std::fs::write(&path, contents)
+        .map_err(|e| AppError::with_cause("Failed to write export file", e))?;
+
+    Ok(path.display().to_string())
+}
+
+#[tauri::command]
+pub fn export_chats_command(
+    db: State<'_, Db>,
+    chat_id: Option<i64>,
+    format: ExportFormat,
+    path: String,
+) -> Result<String, AppError> {
+    export_chats(&*db.conn()?, chat_id, format, &path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::db::test_support::message;
+    use crate::rag::grounding::RetrievedChunk;
+
+    fn export_of(content: &str) -> Vec<ExportedChat> {
+        let mut answer = message(content, false);
+        answer.timestamp = "2024-05-01 09:00:00".to_string();
+        answer.model = Some("<model>.gguf".to_string());
+        answer.sources = vec![RetrievedChunk {
+            filename: "<script>.pdf".to_string(),
+            text: "source \"text\" & <b>more</b>".to_string(),
+            flags: Vec::new(),
+        }];
+
+        vec![ExportedChat {
+            id: 1,
+            name: "<img src=x onerror=alert(1)>".to_string(),
+            created_at: "2024-05-01 09:00:00".to_string(),
+            messages: vec![answer],
+        }]
+    }
+
+    #[test]
+    fn escapes_html_special_characters() {
+        assert_eq!(escape_html("<a href=\"x\">&</a>"), "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;");
+    }
+
+    #[test]
+    fn html_export_escapes_everything_from_the_chat() {
+        let html = render_html(&export_of("Hello <script>alert(1)</script> & `<b>` done"), "<now>");
+
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<img"));
+        assert!(!html.contains("<model>"));
+        assert!(!html.contains("<b>"));
+        assert!(html.contains("<h1>&lt;img src=x onerror=alert(1)&gt;</h1>"));
+        assert!(html.contains("Hello &lt;script&gt;alert(1)&lt;/script&gt; &amp; <code>&lt;b&gt;</code> done"));
+        assert!(html.contains("<strong>&lt;script&gt;.pdf</strong>: source &quot;text&quot; &amp; &lt;b&gt;more&lt;/b&gt;"));
+        assert!(html.contains("&lt;model&gt;.gguf"));
+        assert!(html.contains("Exported from LevChat &lt;now&gt;"));
+    }
+
+    #[test]
+    fn code_blocks_are_escaped_inside_highlighting() {
+        let html = render_html(&export_of("```html\" onload=\"x\n<div class=\"a\">1 && 2</div>\n```"), "now");
+
+        assert!(html.contains("<pre><code class=\"language-html&quot; onload=&quot;x\">"));
+        assert!(!html.contains("<div"));
+        assert!(html.contains("=<span class=\"tok-string\">&quot;a&quot;</span>&gt;"));
+        assert!(html.contains("&amp;&amp;"));
+    }
+}
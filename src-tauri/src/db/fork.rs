@@ -0,0 +1,80 @@
+use rusqlite::{Connection, params};
+use tauri::State;
+use crate::db::attachments::copy_message_attachments;
+use crate::db::chat_settings::{get_chat_settings, set_chat_settings};
+use crate::db::db::{check_chat_writable, create_new_chat, get_message, insert_message, save_message_grounding, Db, Message};
+use crate::db::history::load_branch;
+use crate::error::AppError;
+
+// `message_id` and every message before it, oldest first, whichever branch it is on.
+fn messages_up_to(conn: &Connection, chat_id: i64, message_id: i64) -> Result<Vec<Message>, AppError> {
+    load_branch(
+        conn,
+        "WITH RECURSIVE branch(id, depth) AS (
+            SELECT id, 0 FROM messages WHERE id = ?1 AND chat_id = ?2
+            UNION ALL
+            SELECT p.parent_id, branch.depth + 1 FROM messages p
+            JOIN branch ON p.id = branch.id
+            WHERE p.parent_id IS NOT NULL
+        )",
+        params![message_id, chat_id],
+    )
+}
+
+// Copies the conversation up to and including `message_id` into a new chat, together with
+// the chat's settings and the attachments of the copied messages. The new chat records
+// where it came from; the original is unchanged.
+pub fn fork_chat(conn: &Connection, chat_id: i64, message_id: i64, name: Option<String>) -> Result<i64, AppError> {
+    check_chat_writable(conn, chat_id)?;
+
+    let message = get_message(conn, message_id)?;
+    if message.chat_id != chat_id {
+        return Err(AppError::invalid_input(format!("Message {} is not in chat {}", message_id, chat_id)));
+    }
+
+    let original_name: String = conn.query_row("SELECT name FROM chats WHERE id = ?1", [chat_id], |row| row.get(0))
+        .map_err(|e| AppError::with_cause("Failed to get chat", e))?;
+
+    let name = name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| format!("{} (fork)", original_name));
+
+    let messages = messages_up_to(conn, chat_id, message_id)?;
+    let settings = get_chat_settings(conn, chat_id)?;
+
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| AppError::with_cause("Failed to start transaction", e))?;
+
+    let fork_id = create_new_chat(&tx, name)?;
+    set_chat_settings(&tx, fork_id, &settings)?;
+
+    // The name is chosen here, so automatic titles leave it alone
+    tx.execute(
+        "UPDATE chats SET forked_from_chat_id = ?1, forked_from_message_id = ?2, name_set_by_user = 1
+         WHERE id = ?3",
+        params![chat_id, message_id, fork_id],
+    ).map_err(|e| AppError::with_cause("Failed to record fork origin", e))?;
+
+    let mut parent_id = None;
+    for message in &messages {
+        let copy_id = insert_message(&tx, fork_id, parent_id, message)?;
+        if let Some(grounding) = &message.grounding {
+            save_message_grounding(&tx, copy_id, grounding)?;
+        }
+        if let Some(message_id) = message.id {
+            copy_message_attachments(&tx, message_id, fork_id, copy_id)?;
+        }
+        parent_id = Some(copy_id);
+    }
+
+    tx.commit()
+        .map_err(|e| AppError::with_cause("Failed to save forked chat", e))?;
+
+    Ok(fork_id)
+}
+
+#[tauri::command]
+pub fn fork_chat_command(db: State<'_, Db>, chat_id: i64, message_id: i64, name: Option<String>) -> Result<i64, AppError> {
+    fork_chat(&*db.conn()?, chat_id, message_id, name)
+}
@@ -0,0 +1,295 @@
+use rusqlite::{Connection, params};
+use tauri::State;
+use crate::db::db::{attach_message_sources, message_from_row, Db, Message, MESSAGE_SELECT};
+use crate::error::AppError;
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+// Characters of the last message included in a chat summary
+pub const PREVIEW_CHARS: i64 = 160;
+
+// A window of the chat's active branch, in conversation order.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct MessagePage {
+    pub messages: Vec<Message>,
+    pub has_more_before: bool,
+    pub has_more_after: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct MessagePreview {
+    pub id: i64,
+    pub is_user: bool,
+    pub role: Option<String>,
+    pub preview: String,
+    pub timestamp: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ChatSummary {
+    pub chat_id: i64,
+    pub name: String,
+    // Messages on the active branch
+    pub message_count: i64,
+    pub last_message: Option<MessagePreview>,
+}
+
+// Runs `branch_sql`, a recursive CTE named branch(id, depth) where a higher depth is
+// earlier in the conversation, and loads the messages it selects oldest first.
+pub(crate) fn load_branch(conn: &Connection, branch_sql: &str, params: impl rusqlite::Params) -> Result<Vec<Message>, AppError> {
+    let mut stmt = conn.prepare_cached(
+        &format!("{} {} JOIN branch b ON b.id = m.id ORDER BY b.depth DESC", branch_sql, MESSAGE_SELECT)
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let messages = stmt.query_map(params, message_from_row)
+        .map_err(|e| AppError::with_cause("Failed to query messages", e))?;
+
+    let mut messages = messages.collect::<Result<Vec<Message>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect messages", e))?;
+
+    attach_message_sources(conn, &mut messages)?;
+
+    Ok(messages)
+}
+
+// The last `limit` messages of the active branch.
+fn latest_messages(conn: &Connection, chat_id: i64, limit: u32) -> Result<MessagePage, AppError> {
+    let messages = load_branch(
+        conn,
+        "WITH RECURSIVE branch(id, depth) AS (
+            SELECT active_leaf_id, 0 FROM chats WHERE id = ?1 AND active_leaf_id IS NOT NULL
+            UNION ALL
+            SELECT p.parent_id, branch.depth + 1 FROM messages p
+            JOIN branch ON p.id = branch.id
+            WHERE p.parent_id IS NOT NULL
+            LIMIT ?2
+        )",
+        params![chat_id, limit],
+    )?;
+
+    Ok(MessagePage {
+        has_more_before: matches!(messages.first(), Some(first) if first.parent_id.is_some()),
+        has_more_after: false,
+        messages,
+    })
+}
+
+// Up to `limit` messages directly preceding `message_id`.
+fn messages_before(conn: &Connection, chat_id: i64, message_id: i64, limit: u32) -> Result<MessagePage, AppError> {
+    let messages = load_branch(
+        conn,
+        "WITH RECURSIVE branch(id, depth) AS (
+            SELECT parent_id, 1 FROM messages
+            WHERE id = ?1 AND chat_id = ?2 AND parent_id IS NOT NULL
+            UNION ALL
+            SELECT p.parent_id, branch.depth + 1 FROM messages p
+            JOIN branch ON p.id = branch.id
+            WHERE p.parent_id IS NOT NULL
+            LIMIT ?3
+        )",
+        params![message_id, chat_id, limit],
+    )?;
+
+    Ok(MessagePage {
+        has_more_before: matches!(messages.first(), Some(first) if first.parent_id.is_some()),
+        has_more_after: true,
+        messages,
+    })
+}
+
+// Up to `limit` messages following `message_id` on the active branch.
+fn messages_after(conn: &Connection, chat_id: i64, message_id: i64, limit: u32) -> Result<MessagePage, AppError> {
+    // Walks the ids up from the active leaf and stops at the cursor, which is included so
+    // that a cursor on another branch can be told apart from the end of the chat. Only the
+    // cursor and the `limit + 1` messages after it are loaded.
+    let mut messages = load_branch(
+        conn,
+        "WITH RECURSIVE walk(id, depth) AS (
+            SELECT active_leaf_id, 0 FROM chats WHERE id = ?1 AND active_leaf_id IS NOT NULL
+            UNION ALL
+            SELECT p.parent_id, walk.depth + 1 FROM messages p
+            JOIN walk ON p.id = walk.id
+            WHERE p.parent_id IS NOT NULL AND p.id != ?2
+        ),
+        branch(id, depth) AS (
+            SELECT id, depth FROM walk ORDER BY depth DESC LIMIT ?3
+        )",
+        params![chat_id, message_id, limit as i64 + 2],
+    )?;
+
+    match messages.first() {
+        Some(first) if first.id == Some(message_id) => {}
+        _ => return Err(AppError::invalid_input(format!("Message {} is not on the active branch of chat {}", message_id, chat_id))),
+    }
+
+    messages.remove(0);
+    let has_more_after = messages.len() > limit as usize;
+    messages.truncate(limit as usize);
+
+    Ok(MessagePage {
+        messages,
+        has_more_before: true,
+        has_more_after,
+    })
+}
+
+// Loads part of the active branch: the latest messages, or the ones before or after a cursor.
+pub fn get_chat_messages_page(
+    conn: &Connection,
+    chat_id: i64,
+    before: Option<i64>,
+    after: Option<i64>,
+    limit: u32,
+) -> Result<MessagePage, AppError> {
+    if limit == 0 {
+        return Err(AppError::invalid_input("Page size must be at least 1"));
+    }
+
+    match (before, after) {
+        (Some(_), Some(_)) => Err(AppError::invalid_input("Use either before or after, not both")),
+        (Some(before), None) => messages_before(conn, chat_id, before, limit),
+        (None, Some(after)) => messages_after(conn, chat_id, after, limit),
+        (None, None) => latest_messages(conn, chat_id, limit),
+    }
+}
+
+// Message count and last message of a chat, without loading its history.
+pub fn get_chat_summary(conn: &Connection, chat_id: i64) -> Result<ChatSummary, AppError> {
+    let mut stmt = conn.prepare_cached(
+        "WITH RECURSIVE branch(id) AS (
+            SELECT active_leaf_id FROM chats WHERE id = ?1 AND active_leaf_id IS NOT NULL
+            UNION ALL
+            SELECT p.parent_id FROM messages p
+            JOIN branch ON p.id = branch.id
+            WHERE p.parent_id IS NOT NULL
+        )
+        SELECT c.name, (SELECT COUNT(*) FROM branch),
+               m.id, m.is_user, m.role, substr(m.content, 1, ?2), m.timestamp
+        FROM chats c
+        LEFT JOIN messages m ON m.id = c.active_leaf_id
+        WHERE c.id = ?1"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    stmt.query_row(params![chat_id, PREVIEW_CHARS], |row| {
+        let last_id: Option<i64> = row.get(2)?;
+        let last_message = match last_id {
+            Some(id) => Some(MessagePreview {
+                id,
+                is_user: row.get(3)?,
+                role: row.get(4)?,
+                preview: row.get(5)?,
+                timestamp: row.get(6)?,
+            }),
+            None => None,
+        };
+
+        Ok(ChatSummary {
+            chat_id,
+            name: row.get(0)?,
+            message_count: row.get(1)?,
+            last_message,
+        })
+    }).map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::not_found(format!("Chat {} not found", chat_id)),
+        e => AppError::with_cause("Failed to get chat summary", e),
+    })
+}
+
+#[tauri::command]
+pub fn get_chat_messages_page_command(
+    db: State<'_, Db>,
+    chat_id: i64,
+    before: Option<i64>,
+    after: Option<i64>,
+    limit: Option<u32>,
+) -> Result<MessagePage, AppError> {
+    get_chat_messages_page(&*db.conn()?, chat_id, before, after, limit.unwrap_or(DEFAULT_PAGE_SIZE))
+}
+
+#[tauri::command]
+pub fn get_chat_summary_command(db: State<'_, Db>, chat_id: i64) -> Result<ChatSummary, AppError> {
+    get_chat_summary(&*db.conn()?, chat_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::db::insert_message;
+    use crate::db::db::test_support::{chat_with, memory_db, message};
+
+    fn ids(page: &MessagePage) -> Vec<i64> {
+        page.messages.iter().filter_map(|message| message.id).collect()
+    }
+
+    #[test]
+    fn latest_page_ends_at_the_leaf() {
+        let conn = memory_db();
+        let (chat_id, m) = chat_with(&conn, &["q1", "a1", "q2", "a2", "q3"]);
+
+        let page = get_chat_messages_page(&conn, chat_id, None, None, 2).unwrap();
+        assert_eq!(ids(&page), m[3..].to_vec());
+        assert!(page.has_more_before);
+        assert!(!page.has_more_after);
+
+        let page = get_chat_messages_page(&conn, chat_id, None, None, 10).unwrap();
+        assert_eq!(ids(&page), m);
+        assert!(!page.has_more_before);
+    }
+
+    #[test]
+    fn pages_before_a_cursor_stop_at_the_root() {
+        let conn = memory_db();
+        let (chat_id, m) = chat_with(&conn, &["q1", "a1", "q2", "a2", "q3"]);
+
+        let page = get_chat_messages_page(&conn, chat_id, Some(m[3]), None, 2).unwrap();
+        assert_eq!(ids(&page), vec![m[1], m[2]]);
+        assert!(page.has_more_before);
+        assert!(page.has_more_after);
+
+        let page = get_chat_messages_page(&conn, chat_id, Some(m[1]), None, 5).unwrap();
+        assert_eq!(ids(&page), vec![m[0]]);
+        assert!(!page.has_more_before);
+
+        // Nothing comes before the first message
+        let page = get_chat_messages_page(&conn, chat_id, Some(m[0]), None, 5).unwrap();
+        assert!(page.messages.is_empty());
+        assert!(!page.has_more_before);
+    }
+
+    #[test]
+    fn pages_after_a_cursor_stop_at_the_leaf() {
+        let conn = memory_db();
+        let (chat_id, m) = chat_with(&conn, &["q1", "a1", "q2", "a2", "q3"]);
+
+        let page = get_chat_messages_page(&conn, chat_id, None, Some(m[0]), 2).unwrap();
+        assert_eq!(ids(&page), vec![m[1], m[2]]);
+        assert!(page.has_more_before);
+        assert!(page.has_more_after);
+
+        // Exactly the rest of the branch
+        let page = get_chat_messages_page(&conn, chat_id, None, Some(m[2]), 2).unwrap();
+        assert_eq!(ids(&page), vec![m[3], m[4]]);
+        assert!(!page.has_more_after);
+
+        // Nothing comes after the leaf
+        let page = get_chat_messages_page(&conn, chat_id, None, Some(m[4]), 2).unwrap();
+        assert!(page.messages.is_empty());
+        assert!(!page.has_more_after);
+    }
+
+    #[test]
+    fn cursor_off_the_active_branch_is_rejected() {
+        let conn = memory_db();
+        let (chat_id, m) = chat_with(&conn, &["q1", "a1"]);
+        let (_, other) = chat_with(&conn, &["q1", "a1"]);
+
+        // A second answer to q1 becomes the active branch
+        let answer = insert_message(&conn, chat_id, Some(m[0]), &message("a1 again", false)).unwrap();
+        let page = get_chat_messages_page(&conn, chat_id, None, Some(m[0]), 2).unwrap();
+        assert_eq!(ids(&page), vec![answer]);
+
+        assert!(get_chat_messages_page(&conn, chat_id, None, Some(m[1]), 2).is_err());
+        assert!(get_chat_messages_page(&conn, chat_id, None, Some(other[0]), 2).is_err());
+        assert!(get_chat_messages_page(&conn, chat_id, Some(1), Some(2), 2).is_err());
+        assert!(get_chat_messages_page(&conn, chat_id, None, None, 0).is_err());
+    }
+}
@@ -0,0 +1,404 @@
+use rusqlite::{Connection, params};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use tauri::State;
+use crate::db::db::{current_timestamp, insert_message, save_message_grounding, Db, Message, MessageRole};
+use crate::db::export::{ChatExport, EXPORT_FORMAT};
+use crate::error::AppError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    // LevChat's own JSON export (see db::export)
+    Levchat,
+    // conversations.json from a ChatGPT data export
+    Chatgpt,
+    // One {"role", "content", "timestamp"?, "conversation_id"?, "chat"?} object per line
+    Jsonl,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub format: Option<ImportFormat>,
+    pub chats_imported: usize,
+    pub messages_imported: usize,
+    // Chats that were already imported from the same source
+    pub chats_skipped: Vec<String>,
+    pub messages_skipped: usize,
+    // Human-readable reasons for everything that was skipped
+    pub warnings: Vec<String>,
+}
+
+// A chat parsed from an import file, before it is written to the database
+struct ImportedChat {
+    // Stable identifier within the import source, used to skip re-imports
+    key: String,
+    name: String,
+    created_at: Option<String>,
+    messages: Vec<Message>,
+}
+
+// FNV-1a, stable across Rust releases unlike DefaultHasher
+fn fingerprint<'a>(parts: impl IntoIterator<Item = &'a str>) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for byte in part.bytes().chain(std::iter::once(0)) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+fn epoch_to_timestamp(conn: &Connection, seconds: f64) -> Result<String, AppError> {
+    conn.query_row(
+        "SELECT strftime('%Y-%m-%dT%H:%M:%fZ', ?1, 'unixepoch')",
+        [seconds],
+        |row| row.get(0),
+    ).map_err(|e| AppError::with_cause("Failed to convert timestamp", e))
+}
+
+fn value_to_timestamp(conn: &Connection, value: Option<&Value>) -> Result<Option<String>, AppError> {
+    match value {
+        Some(Value::String(timestamp)) => Ok(Some(timestamp.clone())),
+        Some(Value::Number(seconds)) => match seconds.as_f64() {
+            Some(seconds) => epoch_to_timestamp(conn, seconds).map(Some),
+            None => Ok(None),
+        },
+        _ => Ok(None),
+    }
+}
+
+fn detect_format(contents: &str) -> Result<ImportFormat, AppError> {
+    match serde_json::from_str::<Value>(contents) {
+        Ok(Value::Object(object)) if object.get("format").and_then(Value::as_str) == Some(EXPORT_FORMAT) => {
+            Ok(ImportFormat::Levchat)
+        }
+        Ok(Value::Array(items)) if items.iter().any(|item| item.get("mapping").is_some()) => {
+            Ok(ImportFormat::Chatgpt)
+        }
+        Ok(Value::Object(object)) if object.contains_key("role") => Ok(ImportFormat::Jsonl),
+        Ok(_) => Err(AppError::invalid_input("Unrecognised JSON file, expected a LevChat export or ChatGPT conversations.json")),
+        Err(_) => {
+            let first_line = contents.lines().find(|line| !line.trim().is_empty()).unwrap_or("");
+            match serde_json::from_str::<Value>(first_line) {
+                Ok(Value::Object(object)) if object.contains_key("role") => Ok(ImportFormat::Jsonl),
+                _ => Err(AppError::invalid_input("Unrecognised import file format")),
+            }
+        }
+    }
+}
+
+fn parse_levchat(contents: &str, report: &mut ImportReport) -> Result<Vec<ImportedChat>, AppError> {
+    let export: ChatExport = serde_json::from_str(contents)
+        .map_err(|e| AppError::with_cause("Invalid LevChat export", e))?;
+
+    if export.format != EXPORT_FORMAT {
+        return Err(AppError::invalid_input(format!("Not a LevChat export (format is \"{}\")", export.format)));
+    }
+
+    if export.version > crate::db::export::EXPORT_FORMAT_VERSION {
+        report.warnings.push(format!(
+            "Export version {} is newer than this app supports, unknown fields were ignored",
+            export.version
+        ));
+    }
+
+    Ok(export.chats.into_iter()
+        .map(|chat| {
+            let key = fingerprint(
+                [chat.name.as_str(), chat.created_at.as_str()].into_iter()
+                    .chain(chat.messages.iter().flat_map(|m| [m.timestamp.as_str(), m.content.as_str()]))
+            );
+
+            ImportedChat {
+                key,
+                name: chat.name,
+                created_at: Some(chat.created_at),
+                messages: chat.messages,
+            }
+        })
+        .collect())
+}
+
+fn chatgpt_role(role: &str) -> Option<MessageRole> {
+    match role {
+        "user" => Some(MessageRole::User),
+        "assistant" => Some(MessageRole::Assistant),
+        "system" => Some(MessageRole::System),
+        "tool" => Some(MessageRole::Tool),
+        _ => None,
+    }
+}
+
+fn parse_chatgpt(conn: &Connection, contents: &str, report: &mut ImportReport) -> Result<Vec<ImportedChat>, AppError> {
+    let conversations: Vec<Value> = serde_json::from_str(contents)
+        .map_err(|e| AppError::with_cause("Invalid conversations.json", e))?;
+
+    let mut chats = Vec::new();
+
+    for conversation in conversations {
+        let name = conversation.get("title").and_then(Value::as_str)
+            .unwrap_or("Imported chat")
+            .to_string();
+
+        let key = match conversation.get("conversation_id").or_else(|| conversation.get("id")).and_then(Value::as_str) {
+            Some(id) => id.to_string(),
+            None => {
+                report.warnings.push(format!("\"{}\": no conversation id, skipped", name));
+                continue;
+            }
+        };
+
+        let mapping = match conversation.get("mapping").and_then(Value::as_object) {
+            Some(mapping) => mapping,
+            None => {
+                report.warnings.push(format!("\"{}\": no messages, skipped", name));
+                continue;
+            }
+        };
+
+        // Walk from the selected node back to the root to get the visible branch
+        let mut path = Vec::new();
+        let mut node_id = conversation.get("current_node").and_then(Value::as_str).map(str::to_string);
+        while let Some(id) = node_id {
+            let node = match mapping.get(&id) {
+                Some(node) => node,
+                None => break,
+            };
+            path.push(node);
+            node_id = node.get("parent").and_then(Value::as_str).map(str::to_string);
+        }
+        path.reverse();
+
+        let mut messages = Vec::new();
+        for node in path {
+            let message = match node.get("message") {
+                Some(message) if !message.is_null() => message,
+                _ => continue,
+            };
+
+            let role = message.pointer("/author/role").and_then(Value::as_str).unwrap_or("");
+            let role = match chatgpt_role(role) {
+                Some(role) => role,
+                None => {
+                    report.messages_skipped += 1;
+                    report.warnings.push(format!("\"{}\": message with unknown role \"{}\" skipped", name, role));
+                    continue;
+                }
+            };
+
+            let content_type = message.pointer("/content/content_type").and_then(Value::as_str).unwrap_or("text");
+            let parts = message.pointer("/content/parts").and_then(Value::as_array);
+            let text = parts
+                .map(|parts| parts.iter().filter_map(Value::as_str).collect::<Vec<_>>().join("\n"))
+                .unwrap_or_default();
+
+            if text.trim().is_empty() {
+                // Hidden system prompts and tool scaffolding have no visible text
+                if content_type != "text" || role != MessageRole::System {
+                    report.messages_skipped += 1;
+                    report.warnings.push(format!(
+                        "\"{}\": {} message without text ({}) skipped",
+                        name, role.as_str(), content_type
+                    ));
+                }
+                continue;
+            }
+
+            let timestamp = match value_to_timestamp(conn, message.get("create_time"))? {
+                Some(timestamp) => timestamp,
+                None => current_timestamp(conn)?,
+            };
+
+            messages.push(Message {
+                content: text,
+                is_user: role == MessageRole::User,
+                timestamp,
+                role: Some(role),
+                model: message.pointer("/metadata/model_slug").and_then(Value::as_str).map(str::to_string),
+                ..Default::default()
+            });
+        }
+
+        chats.push(ImportedChat {
+            key,
+            name,
+            created_at: value_to_timestamp(conn, conversation.get("create_time"))?,
+            messages,
+        });
+    }
+
+    Ok(chats)
+}
+
+fn parse_jsonl(conn: &Connection, contents: &str, default_name: &str, report: &mut ImportReport) -> Result<Vec<ImportedChat>, AppError> {
+    // Keeps chats in the order they first appear in the file
+    let mut order: Vec<String> = Vec::new();
+    let mut grouped: HashMap<String, Vec<Message>> = HashMap::new();
+
+    for (line_number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let value: Value = match serde_json::from_str(line) {
+            Ok(value) => value,
+            Err(e) => {
+                report.messages_skipped += 1;
+                report.warnings.push(format!("Line {}: invalid JSON ({}), skipped", line_number + 1, e));
+                continue;
+            }
+        };
+
+        let role = value.get("role").and_then(Value::as_str).and_then(MessageRole::parse);
+        let content = value.get("content").and_then(Value::as_str);
+        let (role, content) = match (role, content) {
+            (Some(role), Some(content)) => (role, content),
+            _ => {
+                report.messages_skipped += 1;
+                report.warnings.push(format!("Line {}: missing or unknown role/content, skipped", line_number + 1));
+                continue;
+            }
+        };
+
+        let chat = value.get("conversation_id")
+            .or_else(|| value.get("chat"))
+            .and_then(Value::as_str)
+            .unwrap_or(default_name)
+            .to_string();
+
+        let timestamp = match value_to_timestamp(conn, value.get("timestamp"))? {
+            Some(timestamp) => timestamp,
+            None => current_timestamp(conn)?,
+        };
+
+        if !grouped.contains_key(&chat) {
+            order.push(chat.clone());
+        }
+
+        grouped.entry(chat).or_default().push(Message {
+            content: content.to_string(),
+            is_user: role == MessageRole::User,
+            timestamp,
+            role: Some(role),
+            model: value.get("model").and_then(Value::as_str).map(str::to_string),
+            ..Default::default()
+        });
+    }
+
+    Ok(order.into_iter()
+        .map(|name| {
+            let messages = grouped.remove(&name).unwrap_or_default();
+            // Lines without a timestamp get the import time, so it is left out of the key
+            // to recognise the same file on a second import
+            let positions: Vec<String> = (0..messages.len()).map(|i| i.to_string()).collect();
+            let key = fingerprint(
+                std::iter::once(name.as_str())
+                    .chain(messages.iter().zip(&positions).flat_map(|(m, position)| [
+                        position.as_str(),
+                        m.role.map(|role| role.as_str()).unwrap_or(""),
+                        m.content.as_str(),
+                    ]))
+            );
+            ImportedChat {
+                key,
+                created_at: messages.first().map(|m| m.timestamp.clone()),
+                name,
+                messages,
+            }
+        })
+        .collect())
+}
+
+fn is_already_imported(conn: &Connection, source: &str, key: &str) -> Result<bool, AppError> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM chats WHERE import_source = ?1 AND import_key = ?2)",
+        params![source, key],
+        |row| row.get(0),
+    ).map_err(|e| AppError::with_cause("Failed to check for previous import", e))
+}
+
+fn store_chats(conn: &Connection, source: &str, chats: Vec<ImportedChat>, report: &mut ImportReport) -> Result<(), AppError> {
+    for chat in chats {
+        if is_already_imported(conn, source, &chat.key)? {
+            report.chats_skipped.push(chat.name);
+            continue;
+        }
+
+        if chat.messages.is_empty() {
+            report.warnings.push(format!("\"{}\": no importable messages, skipped", chat.name));
+            continue;
+        }
+
+        conn.execute(
+            "INSERT INTO chats (name, created_at, import_source, import_key)
+             VALUES (?1, COALESCE(?2, CURRENT_TIMESTAMP), ?3, ?4)",
+            params![chat.name, chat.created_at, source, chat.key],
+        ).map_err(|e| AppError::with_cause("Failed to insert chat", e))?;
+
+        let chat_id = conn.last_insert_rowid();
+        let mut parent_id = None;
+
+        for mut message in chat.messages {
+            message.id = None;
+            message.chat_id = chat_id;
+            message.parent_id = parent_id;
+
+            let message_id = insert_message(conn, chat_id, parent_id, &message)?;
+            if let Some(grounding) = &message.grounding {
+                save_message_grounding(conn, message_id, grounding)?;
+            }
+
+            parent_id = Some(message_id);
+            report.messages_imported += 1;
+        }
+
+        report.chats_imported += 1;
+    }
+
+    Ok(())
+}
+
+pub fn import_chats(conn: &Connection, path: &str, format: Option<ImportFormat>) -> Result<ImportReport, AppError> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| AppError::with_cause("Failed to read import file", e))?;
+
+    let format = match format {
+        Some(format) => format,
+        None => detect_format(&contents)?,
+    };
+
+    let mut report = ImportReport {
+        format: Some(format),
+        ..Default::default()
+    };
+
+    let default_name = Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "Imported chat".to_string());
+
+    let (source, chats) = match format {
+        ImportFormat::Levchat => ("levchat", parse_levchat(&contents, &mut report)?),
+        ImportFormat::Chatgpt => ("chatgpt", parse_chatgpt(conn, &contents, &mut report)?),
+        ImportFormat::Jsonl => ("jsonl", parse_jsonl(conn, &contents, &default_name, &mut report)?),
+    };
+
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| AppError::with_cause("Failed to start transaction", e))?;
+
+    store_chats(&tx, source, chats, &mut report)?;
+
+    tx.commit()
+        .map_err(|e| AppError::with_cause("Failed to commit import", e))?;
+
+    Ok(report)
+}
+
+#[tauri::command]
+pub fn import_chats_command(db: State<'_, Db>, path: String, format: Option<ImportFormat>) -> Result<ImportReport, AppError> {
+    import_chats(&*db.conn()?, &path, format)
+}
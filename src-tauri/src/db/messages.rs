@@ -0,0 +1,257 @@
+use rusqlite::{Connection, params};
+use tauri::State;
+use crate::db::db::{current_timestamp, get_message, Db, Message};
+use crate::db::history::{MessagePreview, PREVIEW_CHARS};
+use crate::error::AppError;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct MessageEdit {
+    pub id: i64,
+    pub message_id: i64,
+    // Content the message had before this edit
+    pub content: String,
+    pub edited_at: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct StarredMessage {
+    pub chat_id: i64,
+    pub chat_name: String,
+    pub message: Message,
+    // The message it answers or follows, to show the star in context
+    pub previous: Option<MessagePreview>,
+}
+
+// Changes the content of a message in place and keeps the old content in its edit history.
+// Unlike edit_message this does not start a new branch.
+pub fn update_message_content(conn: &Connection, message_id: i64, content: String) -> Result<Message, AppError> {
+    if content.trim().is_empty() {
+        return Err(AppError::invalid_input("Message content cannot be empty"));
+    }
+
+    let message = get_message(conn, message_id)?;
+    if message.content == content {
+        return Ok(message);
+    }
+
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| AppError::with_cause("Failed to start transaction", e))?;
+
+    let edited_at = current_timestamp(&tx)?;
+
+    tx.execute(
+        "INSERT INTO message_edits (message_id, content, edited_at) VALUES (?1, ?2, ?3)",
+        params![message_id, message.content, edited_at],
+    ).map_err(|e| AppError::with_cause("Failed to save edit history", e))?;
+
+    tx.execute(
+        "UPDATE messages SET content = ?1, edited_at = ?2 WHERE id = ?3",
+        params![content, edited_at, message_id],
+    ).map_err(|e| AppError::with_cause("Failed to update message", e))?;
+
+    tx.commit()
+        .map_err(|e| AppError::with_cause("Failed to save edit", e))?;
+
+    get_message(conn, message_id)
+}
+
+// Earlier versions of a message, oldest first.
+pub fn get_message_edits(conn: &Connection, message_id: i64) -> Result<Vec<MessageEdit>, AppError> {
+    get_message(conn, message_id)?;
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT id, message_id, content, edited_at FROM message_edits
+         WHERE message_id = ?1 ORDER BY id"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let edits = stmt.query_map([message_id], |row| {
+        Ok(MessageEdit {
+            id: row.get(0)?,
+            message_id: row.get(1)?,
+            content: row.get(2)?,
+            edited_at: row.get(3)?,
+        })
+    }).map_err(|e| AppError::with_cause("Failed to query edit history", e))?;
+
+    edits.collect::<Result<Vec<MessageEdit>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect edit history", e))
+}
+
+// Deletes one message. Its replies are attached to its parent so the rest of the
+// conversation is kept, and the chat's active branch moves up when it ended here.
+pub fn delete_message(conn: &Connection, message_id: i64) -> Result<(), AppError> {
+    let message = get_message(conn, message_id)?;
+
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| AppError::with_cause("Failed to start transaction", e))?;
+
+    tx.execute(
+        "UPDATE messages SET parent_id = ?1 WHERE parent_id = ?2",
+        params![message.parent_id, message_id],
+    ).map_err(|e| AppError::with_cause("Failed to reattach replies", e))?;
+
+    tx.execute(
+        "UPDATE chats SET active_leaf_id = ?1 WHERE id = ?2 AND active_leaf_id = ?3",
+        params![message.parent_id, message.chat_id, message_id],
+    ).map_err(|e| AppError::with_cause("Failed to update active branch", e))?;
+
+    tx.execute("DELETE FROM messages WHERE id = ?1", [message_id])
+        .map_err(|e| AppError::with_cause("Failed to delete message", e))?;
+
+    tx.commit()
+        .map_err(|e| AppError::with_cause("Failed to delete message", e))
+}
+
+// Stars a message, or updates the note of an already starred one.
+pub fn star_message(conn: &Connection, message_id: i64, note: Option<String>) -> Result<(), AppError> {
+    get_message(conn, message_id)?;
+
+    let note = note
+        .map(|note| note.trim().to_string())
+        .filter(|note| !note.is_empty());
+
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO message_stars (message_id, note, starred_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(message_id) DO UPDATE SET note = excluded.note"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    stmt.execute(params![message_id, note, current_timestamp(conn)?])
+        .map_err(|e| AppError::with_cause("Failed to star message", e))?;
+
+    Ok(())
+}
+
+pub fn unstar_message(conn: &Connection, message_id: i64) -> Result<(), AppError> {
+    let mut stmt = conn.prepare_cached("DELETE FROM message_stars WHERE message_id = ?1")
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    stmt.execute([message_id])
+        .map_err(|e| AppError::with_cause("Failed to unstar message", e))?;
+
+    Ok(())
+}
+
+// Starred messages of every chat outside the trash, most recently starred first.
+pub fn list_starred_messages(conn: &Connection) -> Result<Vec<StarredMessage>, AppError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT m.id, c.id, c.name,
+                p.id, p.is_user, p.role, substr(p.content, 1, ?1), p.timestamp
+         FROM message_stars st
+         JOIN messages m ON m.id = st.message_id
+         JOIN chats c ON c.id = m.chat_id
+         LEFT JOIN messages p ON p.id = m.parent_id
+         WHERE c.deleted_at IS NULL
+         ORDER BY st.starred_at DESC, st.message_id DESC"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let rows = stmt.query_map([PREVIEW_CHARS], |row| {
+        let previous_id: Option<i64> = row.get(3)?;
+        let previous = match previous_id {
+            Some(id) => Some(MessagePreview {
+                id,
+                is_user: row.get(4)?,
+                role: row.get(5)?,
+                preview: row.get(6)?,
+                timestamp: row.get(7)?,
+            }),
+            None => None,
+        };
+        Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, String>(2)?, previous))
+    }).map_err(|e| AppError::with_cause("Failed to query starred messages", e))?;
+
+    let rows = rows.collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect starred messages", e))?;
+
+    rows.into_iter()
+        .map(|(message_id, chat_id, chat_name, previous)| {
+            Ok(StarredMessage {
+                chat_id,
+                chat_name,
+                message: get_message(conn, message_id)?,
+                previous,
+            })
+        })
+        .collect()
+}
+
+#[tauri::command]
+pub fn update_message_content_command(db: State<'_, Db>, message_id: i64, content: String) -> Result<Message, AppError> {
+    update_message_content(&*db.conn()?, message_id, content)
+}
+
+#[tauri::command]
+pub fn get_message_edits_command(db: State<'_, Db>, message_id: i64) -> Result<Vec<MessageEdit>, AppError> {
+    get_message_edits(&*db.conn()?, message_id)
+}
+
+#[tauri::command]
+pub fn delete_message_command(db: State<'_, Db>, message_id: i64) -> Result<(), AppError> {
+    delete_message(&*db.conn()?, message_id)
+}
+
+#[tauri::command]
+pub fn star_message_command(db: State<'_, Db>, message_id: i64, note: Option<String>) -> Result<(), AppError> {
+    star_message(&*db.conn()?, message_id, note)
+}
+
+#[tauri::command]
+pub fn unstar_message_command(db: State<'_, Db>, message_id: i64) -> Result<(), AppError> {
+    unstar_message(&*db.conn()?, message_id)
+}
+
+#[tauri::command]
+pub fn list_starred_messages_command(db: State<'_, Db>) -> Result<Vec<StarredMessage>, AppError> {
+    list_starred_messages(&*db.conn()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::db::get_active_leaf;
+    use crate::db::db::test_support::{chat_with, memory_db};
+
+    fn parent_of(conn: &Connection, message_id: i64) -> Option<i64> {
+        get_message(conn, message_id).unwrap().parent_id
+    }
+
+    #[test]
+    fn replies_move_up_to_the_deleted_message_parent() {
+        let conn = memory_db();
+        let (chat_id, m) = chat_with(&conn, &["q1", "a1", "q2"]);
+
+        delete_message(&conn, m[1]).unwrap();
+
+        assert!(get_message(&conn, m[1]).is_err());
+        assert_eq!(parent_of(&conn, m[2]), Some(m[0]));
+        assert_eq!(get_active_leaf(&conn, chat_id).unwrap(), Some(m[2]));
+    }
+
+    #[test]
+    fn deleting_the_root_makes_its_replies_roots() {
+        let conn = memory_db();
+        let (_, m) = chat_with(&conn, &["q1", "a1"]);
+
+        delete_message(&conn, m[0]).unwrap();
+
+        assert_eq!(parent_of(&conn, m[1]), None);
+    }
+
+    #[test]
+    fn deleting_the_leaf_moves_the_active_branch_up() {
+        let conn = memory_db();
+        let (chat_id, m) = chat_with(&conn, &["q1", "a1", "q2"]);
+
+        delete_message(&conn, m[2]).unwrap();
+        assert_eq!(get_active_leaf(&conn, chat_id).unwrap(), Some(m[1]));
+
+        delete_message(&conn, m[1]).unwrap();
+        delete_message(&conn, m[0]).unwrap();
+        assert_eq!(get_active_leaf(&conn, chat_id).unwrap(), None);
+    }
+
+    #[test]
+    fn missing_messages_are_not_found() {
+        let conn = memory_db();
+        assert!(delete_message(&conn, 42).is_err());
+    }
+}
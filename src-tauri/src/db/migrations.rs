@@ -0,0 +1,652 @@
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::error::{AppError, ErrorCode};
+
+// Copies of chats.db taken before migrating, older ones are deleted
+const MIGRATION_BACKUPS_KEPT: usize = 3;
+const MIGRATION_BACKUP_PREFIX: &str = "chats-v";
+
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+// Ordered list of schema changes. Never edit a released migration, append a new one instead.
+// Versions 1 and 2 use IF NOT EXISTS because they describe tables that were created
+// before chats.db was versioned.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create chats and messages tables",
+        sql: "CREATE TABLE IF NOT EXISTS chats (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );
+            CREATE TABLE IF NOT EXISTS messages (
+                id INTEGER PRIMARY KEY,
+                chat_id INTEGER NOT NULL,
+                content TEXT NOT NULL,
+                is_user BOOLEAN NOT NULL,
+                timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY(chat_id) REFERENCES chats(id)
+            );",
+    },
+    Migration {
+        version: 2,
+        description: "Create message grounding and routing tables",
+        sql: "CREATE TABLE IF NOT EXISTS message_grounding (
+                message_id INTEGER PRIMARY KEY,
+                score REAL NOT NULL,
+                unsupported_claims TEXT NOT NULL,
+                checked_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY(message_id) REFERENCES messages(id)
+            );
+            CREATE TABLE IF NOT EXISTS message_routing (
+                message_id INTEGER PRIMARY KEY,
+                retrieve BOOLEAN NOT NULL,
+                reason TEXT NOT NULL,
+                overridden BOOLEAN NOT NULL,
+                similarity REAL,
+                FOREIGN KEY(message_id) REFERENCES messages(id)
+            );",
+    },
+    Migration {
+        version: 3,
+        description: "Add full-text search over message content and chat names",
+        sql: "CREATE VIRTUAL TABLE messages_fts USING fts5(
+                content,
+                content = 'messages',
+                content_rowid = 'id',
+                tokenize = 'unicode61 remove_diacritics 2',
+                prefix = '2 3'
+            );
+            CREATE VIRTUAL TABLE chats_fts USING fts5(
+                name,
+                content = 'chats',
+                content_rowid = 'id',
+                tokenize = 'unicode61 remove_diacritics 2',
+                prefix = '2 3'
+            );
+
+            CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+                INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content);
+            END;
+            CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+                INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+            END;
+            CREATE TRIGGER messages_fts_update AFTER UPDATE OF content ON messages BEGIN
+                INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+                INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content);
+            END;
+
+            CREATE TRIGGER chats_fts_insert AFTER INSERT ON chats BEGIN
+                INSERT INTO chats_fts (rowid, name) VALUES (new.id, new.name);
+            END;
+            CREATE TRIGGER chats_fts_delete AFTER DELETE ON chats BEGIN
+                INSERT INTO chats_fts (chats_fts, rowid, name) VALUES ('delete', old.id, old.name);
+            END;
+            CREATE TRIGGER chats_fts_update AFTER UPDATE OF name ON chats BEGIN
+                INSERT INTO chats_fts (chats_fts, rowid, name) VALUES ('delete', old.id, old.name);
+                INSERT INTO chats_fts (rowid, name) VALUES (new.id, new.name);
+            END;
+
+            INSERT INTO messages_fts (messages_fts) VALUES ('rebuild');
+            INSERT INTO chats_fts (chats_fts) VALUES ('rebuild');",
+    },
+    Migration {
+        version: 4,
+        description: "Record role, model, sampling, token counts, timings and sources per message",
+        sql: "ALTER TABLE messages ADD COLUMN role TEXT;
+            ALTER TABLE messages ADD COLUMN model TEXT;
+            ALTER TABLE messages ADD COLUMN sampling_params TEXT;
+            ALTER TABLE messages ADD COLUMN prompt_tokens INTEGER;
+            ALTER TABLE messages ADD COLUMN completion_tokens INTEGER;
+            ALTER TABLE messages ADD COLUMN time_to_first_token_ms INTEGER;
+            ALTER TABLE messages ADD COLUMN latency_ms INTEGER;
+            UPDATE messages SET role = CASE WHEN is_user THEN 'user' ELSE 'assistant' END;
+
+            CREATE TABLE message_sources (
+                id INTEGER PRIMARY KEY,
+                message_id INTEGER NOT NULL,
+                position INTEGER NOT NULL,
+                filename TEXT NOT NULL,
+                text TEXT NOT NULL,
+                flags TEXT,
+                FOREIGN KEY(message_id) REFERENCES messages(id)
+            );
+            CREATE INDEX idx_message_sources_message ON message_sources(message_id);",
+    },
+    Migration {
+        version: 5,
+        description: "Store messages as a tree with an active branch per chat",
+        sql: "ALTER TABLE messages ADD COLUMN parent_id INTEGER REFERENCES messages(id);
+            ALTER TABLE chats ADD COLUMN active_leaf_id INTEGER REFERENCES messages(id);
+
+            UPDATE messages SET parent_id = (
+                SELECT p.id FROM messages p
+                WHERE p.chat_id = messages.chat_id
+                  AND (p.timestamp < messages.timestamp
+                       OR (p.timestamp = messages.timestamp AND p.id < messages.id))
+                ORDER BY p.timestamp DESC, p.id DESC
+                LIMIT 1
+            );
+            UPDATE chats SET active_leaf_id = (
+                SELECT m.id FROM messages m
+                WHERE m.chat_id = chats.id
+                ORDER BY m.timestamp DESC, m.id DESC
+                LIMIT 1
+            );
+
+            CREATE INDEX idx_messages_parent ON messages(parent_id);",
+    },
+    Migration {
+        version: 6,
+        description: "Remember where imported chats came from",
+        sql: "ALTER TABLE chats ADD COLUMN import_source TEXT;
+            ALTER TABLE chats ADD COLUMN import_key TEXT;
+            CREATE UNIQUE INDEX idx_chats_import ON chats(import_source, import_key)
+                WHERE import_key IS NOT NULL;",
+    },
+    // SQLite cannot add ON DELETE CASCADE to existing tables, so they are rebuilt.
+    // Rows that already point at missing chats or messages are dropped on the way.
+    Migration {
+        version: 7,
+        description: "Cascade deletes from chats to messages and add a trash",
+        sql: "CREATE TABLE chats_new (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                active_leaf_id INTEGER REFERENCES messages(id) ON DELETE SET NULL,
+                import_source TEXT,
+                import_key TEXT,
+                deleted_at DATETIME
+            );
+            INSERT INTO chats_new (id, name, created_at, active_leaf_id, import_source, import_key)
+                SELECT id, name, created_at,
+                       CASE WHEN active_leaf_id IN (SELECT id FROM messages) THEN active_leaf_id END,
+                       import_source, import_key
+                FROM chats;
+
+            CREATE TABLE messages_new (
+                id INTEGER PRIMARY KEY,
+                chat_id INTEGER NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+                content TEXT NOT NULL,
+                is_user BOOLEAN NOT NULL,
+                timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+                role TEXT,
+                model TEXT,
+                sampling_params TEXT,
+                prompt_tokens INTEGER,
+                completion_tokens INTEGER,
+                time_to_first_token_ms INTEGER,
+                latency_ms INTEGER,
+                parent_id INTEGER REFERENCES messages(id) ON DELETE CASCADE
+            );
+            INSERT INTO messages_new
+                SELECT id, chat_id, content, is_user, timestamp, role, model, sampling_params,
+                       prompt_tokens, completion_tokens, time_to_first_token_ms, latency_ms,
+                       CASE WHEN parent_id IN (SELECT id FROM messages) THEN parent_id END
+                FROM messages
+                WHERE chat_id IN (SELECT id FROM chats);
+
+            CREATE TABLE message_grounding_new (
+                message_id INTEGER PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+                score REAL NOT NULL,
+                unsupported_claims TEXT NOT NULL,
+                checked_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );
+            INSERT INTO message_grounding_new
+                SELECT message_id, score, unsupported_claims, checked_at FROM message_grounding
+                WHERE message_id IN (SELECT id FROM messages_new);
+
+            CREATE TABLE message_routing_new (
+                message_id INTEGER PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+                retrieve BOOLEAN NOT NULL,
+                reason TEXT NOT NULL,
+                overridden BOOLEAN NOT NULL,
+                similarity REAL
+            );
+            INSERT INTO message_routing_new
+                SELECT message_id, retrieve, reason, overridden, similarity FROM message_routing
+                WHERE message_id IN (SELECT id FROM messages_new);
+
+            CREATE TABLE message_sources_new (
+                id INTEGER PRIMARY KEY,
+                message_id INTEGER NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+                position INTEGER NOT NULL,
+                filename TEXT NOT NULL,
+                text TEXT NOT NULL,
+                flags TEXT
+            );
+            INSERT INTO message_sources_new
+                SELECT id, message_id, position, filename, text, flags FROM message_sources
+                WHERE message_id IN (SELECT id FROM messages_new);
+
+            DROP TABLE message_sources;
+            DROP TABLE message_routing;
+            DROP TABLE message_grounding;
+            DROP TABLE messages;
+            DROP TABLE chats;
+            ALTER TABLE chats_new RENAME TO chats;
+            ALTER TABLE messages_new RENAME TO messages;
+            ALTER TABLE message_grounding_new RENAME TO message_grounding;
+            ALTER TABLE message_routing_new RENAME TO message_routing;
+            ALTER TABLE message_sources_new RENAME TO message_sources;
+
+            CREATE INDEX idx_messages_chat ON messages(chat_id);
+            CREATE INDEX idx_messages_parent ON messages(parent_id);
+            CREATE INDEX idx_message_sources_message ON message_sources(message_id);
+            CREATE UNIQUE INDEX idx_chats_import ON chats(import_source, import_key)
+                WHERE import_key IS NOT NULL;
+            CREATE INDEX idx_chats_deleted ON chats(deleted_at) WHERE deleted_at IS NOT NULL;
+
+            CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+                INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content);
+            END;
+            CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+                INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+            END;
+            CREATE TRIGGER messages_fts_update AFTER UPDATE OF content ON messages BEGIN
+                INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+                INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content);
+            END;
+
+            CREATE TRIGGER chats_fts_insert AFTER INSERT ON chats BEGIN
+                INSERT INTO chats_fts (rowid, name) VALUES (new.id, new.name);
+            END;
+            CREATE TRIGGER chats_fts_delete AFTER DELETE ON chats BEGIN
+                INSERT INTO chats_fts (chats_fts, rowid, name) VALUES ('delete', old.id, old.name);
+            END;
+            CREATE TRIGGER chats_fts_update AFTER UPDATE OF name ON chats BEGIN
+                INSERT INTO chats_fts (chats_fts, rowid, name) VALUES ('delete', old.id, old.name);
+                INSERT INTO chats_fts (rowid, name) VALUES (new.id, new.name);
+            END;
+
+            INSERT INTO messages_fts (messages_fts) VALUES ('rebuild');
+            INSERT INTO chats_fts (chats_fts) VALUES ('rebuild');",
+    },
+    Migration {
+        version: 8,
+        description: "Store app settings in chats.db",
+        sql: "CREATE TABLE app_settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );",
+    },
+    Migration {
+        version: 9,
+        description: "Organize chats with folders, tags, pinning, archiving and last activity",
+        sql: "CREATE TABLE folders (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );
+            CREATE TABLE chat_tags (
+                chat_id INTEGER NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+                tag TEXT NOT NULL COLLATE NOCASE,
+                PRIMARY KEY (chat_id, tag)
+            );
+            CREATE INDEX idx_chat_tags_tag ON chat_tags(tag);
+
+            ALTER TABLE chats ADD COLUMN folder_id INTEGER REFERENCES folders(id) ON DELETE SET NULL;
+            ALTER TABLE chats ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT 0;
+            ALTER TABLE chats ADD COLUMN archived BOOLEAN NOT NULL DEFAULT 0;
+            ALTER TABLE chats ADD COLUMN last_activity_at DATETIME;
+            CREATE INDEX idx_chats_folder ON chats(folder_id);
+
+            UPDATE chats SET last_activity_at = COALESCE(
+                (SELECT MAX(m.timestamp) FROM messages m WHERE m.chat_id = chats.id),
+                created_at
+            );
+
+            CREATE TRIGGER chats_last_activity AFTER INSERT ON messages BEGIN
+                UPDATE chats SET last_activity_at = MAX(COALESCE(last_activity_at, ''), new.timestamp)
+                WHERE id = new.chat_id;
+            END;",
+    },
+    Migration {
+        version: 10,
+        description: "Store settings per chat",
+        sql: "CREATE TABLE chat_settings (
+                chat_id INTEGER PRIMARY KEY REFERENCES chats(id) ON DELETE CASCADE,
+                system_prompt TEXT,
+                model TEXT,
+                embedding_model TEXT,
+                sampling_params TEXT,
+                rag_options TEXT,
+                prompt_template TEXT,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );",
+    },
+    // Chats still called "New Chat" never got a real name, every other name is kept
+    Migration {
+        version: 11,
+        description: "Track whether the user named a chat",
+        sql: "ALTER TABLE chats ADD COLUMN name_set_by_user BOOLEAN NOT NULL DEFAULT 0;
+            UPDATE chats SET name_set_by_user = 1 WHERE name != 'New Chat';",
+    },
+    // message_edits keeps the content each message had before every edit
+    Migration {
+        version: 12,
+        description: "Add message edit history and stars",
+        sql: "ALTER TABLE messages ADD COLUMN edited_at DATETIME;
+
+            CREATE TABLE message_edits (
+                id INTEGER PRIMARY KEY,
+                message_id INTEGER NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+                content TEXT NOT NULL,
+                edited_at DATETIME NOT NULL
+            );
+            CREATE INDEX idx_message_edits_message ON message_edits(message_id);
+
+            CREATE TABLE message_stars (
+                message_id INTEGER PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+                note TEXT,
+                starred_at DATETIME NOT NULL
+            );
+            CREATE INDEX idx_message_stars_starred_at ON message_stars(starred_at);",
+    },
+    Migration {
+        version: 13,
+        description: "Record where forked chats came from",
+        sql: "ALTER TABLE chats ADD COLUMN forked_from_chat_id INTEGER REFERENCES chats(id) ON DELETE SET NULL;
+            ALTER TABLE chats ADD COLUMN forked_from_message_id INTEGER REFERENCES messages(id) ON DELETE SET NULL;
+            CREATE INDEX idx_chats_forked_from ON chats(forked_from_chat_id);",
+    },
+    Migration {
+        version: 14,
+        description: "Add message attachments",
+        sql: "CREATE TABLE attachments (
+                id INTEGER PRIMARY KEY,
+                chat_id INTEGER NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+                message_id INTEGER REFERENCES messages(id) ON DELETE CASCADE,
+                filename TEXT NOT NULL,
+                sha256 TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                mode TEXT NOT NULL,
+                created_at DATETIME NOT NULL
+            );
+            CREATE INDEX idx_attachments_chat ON attachments(chat_id);
+            CREATE INDEX idx_attachments_message ON attachments(message_id);
+
+            CREATE TABLE attachment_chunks (
+                id INTEGER PRIMARY KEY,
+                attachment_id INTEGER NOT NULL REFERENCES attachments(id) ON DELETE CASCADE,
+                position INTEGER NOT NULL,
+                text TEXT NOT NULL
+            );
+            CREATE INDEX idx_attachment_chunks_attachment ON attachment_chunks(attachment_id, position);
+
+            CREATE VIRTUAL TABLE attachment_chunks_fts USING fts5(
+                text,
+                content = 'attachment_chunks',
+                content_rowid = 'id',
+                tokenize = 'unicode61 remove_diacritics 2'
+            );
+            CREATE TRIGGER attachment_chunks_fts_insert AFTER INSERT ON attachment_chunks BEGIN
+                INSERT INTO attachment_chunks_fts (rowid, text) VALUES (new.id, new.text);
+            END;
+            CREATE TRIGGER attachment_chunks_fts_delete AFTER DELETE ON attachment_chunks BEGIN
+                INSERT INTO attachment_chunks_fts (attachment_chunks_fts, rowid, text) VALUES ('delete', old.id, old.text);
+            END;",
+    },
+];
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+pub fn schema_version(conn: &Connection) -> Result<i64, AppError> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+        .map_err(|e| AppError::with_cause("Failed to read schema version", e))
+}
+
+fn has_tables(conn: &Connection) -> Result<bool, AppError> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%')",
+        [],
+        |row| row.get(0),
+    ).map_err(|e| AppError::with_cause("Failed to inspect database", e))
+}
+
+fn backup_dir(db_path: &Path) -> Result<PathBuf, AppError> {
+    Ok(db_path.parent()
+        .ok_or_else(|| AppError::internal("Failed to get database directory"))?
+        .join("backups"))
+}
+
+fn backup_path(db_path: &Path, version: i64) -> Result<PathBuf, AppError> {
+    let backup_dir = backup_dir(db_path)?;
+
+    std::fs::create_dir_all(&backup_dir)
+        .map_err(|e| AppError::with_cause("Failed to create backup directory", e))?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    Ok(backup_dir.join(format!("{}{}-{}.db", MIGRATION_BACKUP_PREFIX, version, timestamp)))
+}
+
+// Writes a consistent copy of the database next to it before it is migrated.
+fn backup_database(conn: &Connection, db_path: &Path, version: i64) -> Result<PathBuf, AppError> {
+    let backup_path = backup_path(db_path, version)?;
+
+    conn.execute("VACUUM INTO ?1", [backup_path.to_string_lossy()])
+        .map_err(|e| AppError::with_cause("Failed to back up database before migration", e))?;
+
+    Ok(backup_path)
+}
+
+// Deletes the oldest pre-migration copies so that at most MIGRATION_BACKUPS_KEPT remain.
+fn rotate_migration_backups(db_path: &Path) -> Result<usize, AppError> {
+    let entries = std::fs::read_dir(backup_dir(db_path)?)
+        .map_err(|e| AppError::with_cause("Failed to read backup directory", e))?;
+
+    let mut backups: Vec<(SystemTime, PathBuf)> = entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.starts_with(MIGRATION_BACKUP_PREFIX) && name.ends_with(".db")
+        })
+        .map(|entry| {
+            let modified = entry.metadata().and_then(|m| m.modified()).unwrap_or(UNIX_EPOCH);
+            (modified, entry.path())
+        })
+        .collect();
+
+    backups.sort();
+    let excess = backups.len().saturating_sub(MIGRATION_BACKUPS_KEPT);
+
+    for (_, path) in &backups[..excess] {
+        std::fs::remove_file(path)
+            .map_err(|e| AppError::with_cause(format!("Failed to delete old backup {}", path.display()), e))?;
+    }
+
+    Ok(excess)
+}
+
+// Brings the database up to the latest schema version, one migration at a time.
+// Foreign keys are off while migrating, otherwise table rebuilds would cascade.
+// The caller turns them back on.
+pub fn run_migrations(conn: &Connection, db_path: &Path) -> Result<(), AppError> {
+    let current = schema_version(conn)?;
+    let latest = latest_version();
+
+    if current > latest {
+        return Err(AppError::new(ErrorCode::Database, format!(
+            "chats.db uses schema version {} but this version of LevChat only supports up to version {}. \
+             Please update LevChat to open this database.",
+            current, latest
+        )));
+    }
+
+    // Can only be changed outside a transaction
+    conn.pragma_update(None, "foreign_keys", false)
+        .map_err(|e| AppError::with_cause("Failed to disable foreign keys", e))?;
+
+    let pending: Vec<&Migration> = MIGRATIONS.iter().filter(|m| m.version > current).collect();
+
+    // One copy of the database as it was before any pending migration. Nothing to protect
+    // in a brand new database.
+    if !pending.is_empty() && (current > 0 || has_tables(conn)?) {
+        let backup = backup_database(conn, db_path, current)?;
+        log::info!("Backed up chats.db to {}", backup.display());
+
+        if let Err(e) = rotate_migration_backups(db_path) {
+            log::warn!("Failed to remove old migration backups: {}", e);
+        }
+    }
+
+    for migration in pending {
+        let tx = conn.unchecked_transaction()
+            .map_err(|e| AppError::with_cause(format!("Failed to start migration {}", migration.version), e))?;
+
+        tx.execute_batch(migration.sql)
+            .map_err(|e| AppError::with_cause(
+                format!("Migration {} ({}) failed", migration.version, migration.description),
+                e,
+            ))?;
+
+        tx.pragma_update(None, "user_version", migration.version)
+            .map_err(|e| AppError::with_cause(format!("Failed to record schema version {}", migration.version), e))?;
+
+        tx.commit()
+            .map_err(|e| AppError::with_cause(format!("Failed to commit migration {}", migration.version), e))?;
+
+        log::info!("Applied migration {}: {}", migration.version, migration.description);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_db(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("levchat-migrations-{}-{}", std::process::id(), name));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("chats.db")
+    }
+
+    fn table_exists(conn: &Connection, name: &str) -> bool {
+        conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = ?1)",
+            [name],
+            |row| row.get(0),
+        ).unwrap()
+    }
+
+    fn backups(db_path: &Path) -> Vec<String> {
+        let mut names: Vec<String> = match std::fs::read_dir(backup_dir(db_path).unwrap()) {
+            Ok(entries) => entries.flatten().map(|e| e.file_name().to_string_lossy().to_string()).collect(),
+            Err(_) => Vec::new(),
+        };
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn versions_are_ordered() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i64 + 1, "{}", migration.description);
+        }
+    }
+
+    #[test]
+    fn fresh_database_reaches_latest_version() {
+        let path = temp_db("fresh");
+        let conn = Connection::open(&path).unwrap();
+
+        run_migrations(&conn, &path).unwrap();
+
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+        for table in ["chats", "messages", "messages_fts", "message_sources", "attachments", "attachment_chunks_fts"] {
+            assert!(table_exists(&conn, table), "{} missing", table);
+        }
+        // Nothing to back up in a new database
+        assert!(backups(&path).is_empty());
+
+        // Running again changes nothing
+        run_migrations(&conn, &path).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn legacy_database_is_upgraded_with_one_backup() {
+        let path = temp_db("legacy");
+        let conn = Connection::open(&path).unwrap();
+
+        // Schema of the releases before chats.db was versioned
+        conn.execute_batch(
+            "CREATE TABLE chats (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );
+            CREATE TABLE messages (
+                id INTEGER PRIMARY KEY,
+                chat_id INTEGER NOT NULL,
+                content TEXT NOT NULL,
+                is_user BOOLEAN NOT NULL,
+                timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY(chat_id) REFERENCES chats(id)
+            );
+            INSERT INTO chats (id, name) VALUES (1, 'Old chat'), (2, 'New Chat');
+            INSERT INTO messages (chat_id, content, is_user) VALUES (1, 'hello world', 1), (1, 'hi', 0);"
+        ).unwrap();
+
+        // Copies left by earlier upgrades
+        std::fs::create_dir_all(backup_dir(&path).unwrap()).unwrap();
+        for i in 0..4 {
+            std::fs::write(backup_dir(&path).unwrap().join(format!("chats-v9-{}.db", i)), b"old").unwrap();
+        }
+
+        run_migrations(&conn, &path).unwrap();
+
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+
+        let names: Vec<(String, bool)> = conn.prepare("SELECT name, name_set_by_user FROM chats ORDER BY id").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(names, vec![("Old chat".to_string(), true), ("New Chat".to_string(), false)]);
+
+        let found: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM messages_fts WHERE messages_fts MATCH 'hello'",
+            [],
+            |row| row.get(0),
+        ).unwrap();
+        assert_eq!(found, 1);
+
+        let backups = backups(&path);
+        assert_eq!(backups.len(), MIGRATION_BACKUPS_KEPT);
+        assert_eq!(backups.iter().filter(|name| name.starts_with("chats-v0-")).count(), 1);
+
+        let backup = Connection::open(backup_dir(&path).unwrap().join(&backups[0])).unwrap();
+        assert_eq!(schema_version(&backup).unwrap(), 0);
+        let messages: i64 = backup.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0)).unwrap();
+        assert_eq!(messages, 2);
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn newer_database_is_rejected() {
+        let path = temp_db("newer");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch("CREATE TABLE chats (id INTEGER PRIMARY KEY, name TEXT NOT NULL);").unwrap();
+        conn.pragma_update(None, "user_version", latest_version() + 1).unwrap();
+
+        let error = run_migrations(&conn, &path).err().unwrap();
+        assert_eq!(error.code, ErrorCode::Database);
+        assert_eq!(schema_version(&conn).unwrap(), latest_version() + 1);
+        assert!(backups(&path).is_empty());
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}
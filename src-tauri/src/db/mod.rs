@@ -1 +1,19 @@
-pub mod db;
\ No newline at end of file
+pub mod db;
+pub mod migrations;
+pub mod search;
+pub mod branches;
+pub mod export;
+pub mod import;
+pub mod settings;
+pub mod trash;
+pub mod organize;
+pub mod history;
+pub mod chat_settings;
+pub mod titles;
+pub mod backup;
+pub mod encryption;
+pub mod analytics;
+pub mod messages;
+pub mod fork;
+pub mod retention;
+pub mod attachments;
@@ -0,0 +1,213 @@
+use rusqlite::{Connection, params};
+use tauri::State;
+use crate::db::db::Db;
+use crate::error::AppError;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Folder {
+    pub id: i64,
+    pub name: String,
+    pub created_at: String,
+    pub chat_count: i64,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub chat_count: i64,
+}
+
+fn check_updated(updated: usize, what: &str, id: i64) -> Result<(), AppError> {
+    if updated == 0 {
+        return Err(AppError::not_found(format!("{} {} not found", what, id)));
+    }
+    Ok(())
+}
+
+pub fn list_folders(conn: &Connection) -> Result<Vec<Folder>, AppError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT f.id, f.name, f.created_at,
+                (SELECT COUNT(*) FROM chats c WHERE c.folder_id = f.id AND c.deleted_at IS NULL)
+         FROM folders f
+         ORDER BY f.name COLLATE NOCASE"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let folders = stmt.query_map([], |row| {
+        Ok(Folder {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            created_at: row.get(2)?,
+            chat_count: row.get(3)?,
+        })
+    }).map_err(|e| AppError::with_cause("Failed to query folders", e))?;
+
+    folders.collect::<Result<Vec<Folder>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect folders", e))
+}
+
+pub fn create_folder(conn: &Connection, name: &str) -> Result<i64, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::invalid_input("Folder name cannot be empty"));
+    }
+
+    let mut stmt = conn.prepare_cached("INSERT INTO folders (name) VALUES (?1)")
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    stmt.execute([name])
+        .map_err(|e| AppError::with_cause("Failed to create folder", e))?;
+
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn rename_folder(conn: &Connection, folder_id: i64, name: &str) -> Result<(), AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::invalid_input("Folder name cannot be empty"));
+    }
+
+    let mut stmt = conn.prepare_cached("UPDATE folders SET name = ?1 WHERE id = ?2")
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let updated = stmt.execute(params![name, folder_id])
+        .map_err(|e| AppError::with_cause("Failed to rename folder", e))?;
+
+    check_updated(updated, "Folder", folder_id)
+}
+
+// Deletes the folder only, its chats move back to the top level.
+pub fn delete_folder(conn: &Connection, folder_id: i64) -> Result<(), AppError> {
+    let mut stmt = conn.prepare_cached("DELETE FROM folders WHERE id = ?1")
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let deleted = stmt.execute([folder_id])
+        .map_err(|e| AppError::with_cause("Failed to delete folder", e))?;
+
+    check_updated(deleted, "Folder", folder_id)
+}
+
+// Moves a chat into a folder, or out of any folder when `folder_id` is None.
+pub fn move_chat_to_folder(conn: &Connection, chat_id: i64, folder_id: Option<i64>) -> Result<(), AppError> {
+    let mut stmt = conn.prepare_cached("UPDATE chats SET folder_id = ?1 WHERE id = ?2")
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let updated = stmt.execute(params![folder_id, chat_id])
+        .map_err(|e| AppError::with_cause("Failed to move chat", e))?;
+
+    check_updated(updated, "Chat", chat_id)
+}
+
+pub fn set_chat_pinned(conn: &Connection, chat_id: i64, pinned: bool) -> Result<(), AppError> {
+    let mut stmt = conn.prepare_cached("UPDATE chats SET pinned = ?1 WHERE id = ?2")
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let updated = stmt.execute(params![pinned, chat_id])
+        .map_err(|e| AppError::with_cause("Failed to pin chat", e))?;
+
+    check_updated(updated, "Chat", chat_id)
+}
+
+pub fn set_chat_archived(conn: &Connection, chat_id: i64, archived: bool) -> Result<(), AppError> {
+    let mut stmt = conn.prepare_cached("UPDATE chats SET archived = ?1 WHERE id = ?2")
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let updated = stmt.execute(params![archived, chat_id])
+        .map_err(|e| AppError::with_cause("Failed to archive chat", e))?;
+
+    check_updated(updated, "Chat", chat_id)
+}
+
+// Replaces the chat's tags. Tags are trimmed and compared case-insensitively.
+pub fn set_chat_tags(conn: &Connection, chat_id: i64, tags: Vec<String>) -> Result<(), AppError> {
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| AppError::with_cause("Failed to start transaction", e))?;
+
+    let exists: bool = tx.query_row("SELECT EXISTS (SELECT 1 FROM chats WHERE id = ?1)", [chat_id], |row| row.get(0))
+        .map_err(|e| AppError::with_cause("Failed to get chat", e))?;
+    if !exists {
+        return Err(AppError::not_found(format!("Chat {} not found", chat_id)));
+    }
+
+    tx.execute("DELETE FROM chat_tags WHERE chat_id = ?1", [chat_id])
+        .map_err(|e| AppError::with_cause("Failed to clear tags", e))?;
+
+    {
+        let mut stmt = tx.prepare_cached("INSERT OR IGNORE INTO chat_tags (chat_id, tag) VALUES (?1, ?2)")
+            .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+        for tag in tags.iter().map(|tag| tag.trim()).filter(|tag| !tag.is_empty()) {
+            stmt.execute(params![chat_id, tag])
+                .map_err(|e| AppError::with_cause("Failed to add tag", e))?;
+        }
+    }
+
+    tx.commit()
+        .map_err(|e| AppError::with_cause("Failed to save tags", e))
+}
+
+// Every tag in use, for autocompletion and the tag filter
+pub fn list_tags(conn: &Connection) -> Result<Vec<TagCount>, AppError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT t.tag, COUNT(*)
+         FROM chat_tags t
+         JOIN chats c ON c.id = t.chat_id
+         WHERE c.deleted_at IS NULL
+         GROUP BY t.tag
+         ORDER BY t.tag"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let tags = stmt.query_map([], |row| {
+        Ok(TagCount {
+            tag: row.get(0)?,
+            chat_count: row.get(1)?,
+        })
+    }).map_err(|e| AppError::with_cause("Failed to query tags", e))?;
+
+    tags.collect::<Result<Vec<TagCount>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect tags", e))
+}
+
+#[tauri::command]
+pub fn list_folders_command(db: State<'_, Db>) -> Result<Vec<Folder>, AppError> {
+    list_folders(&*db.conn()?)
+}
+
+#[tauri::command]
+pub fn create_folder_command(db: State<'_, Db>, name: String) -> Result<i64, AppError> {
+    create_folder(&*db.conn()?, &name)
+}
+
+#[tauri::command]
+pub fn rename_folder_command(db: State<'_, Db>, folder_id: i64, name: String) -> Result<(), AppError> {
+    rename_folder(&*db.conn()?, folder_id, &name)
+}
+
+#[tauri::command]
+pub fn delete_folder_command(db: State<'_, Db>, folder_id: i64) -> Result<(), AppError> {
+    delete_folder(&*db.conn()?, folder_id)
+}
+
+#[tauri::command]
+pub fn move_chat_to_folder_command(db: State<'_, Db>, chat_id: i64, folder_id: Option<i64>) -> Result<(), AppError> {
+    move_chat_to_folder(&*db.conn()?, chat_id, folder_id)
+}
+
+#[tauri::command]
+pub fn set_chat_pinned_command(db: State<'_, Db>, chat_id: i64, pinned: bool) -> Result<(), AppError> {
+    set_chat_pinned(&*db.conn()?, chat_id, pinned)
+}
+
+#[tauri::command]
+pub fn set_chat_archived_command(db: State<'_, Db>, chat_id: i64, archived: bool) -> Result<(), AppError> {
+    set_chat_archived(&*db.conn()?, chat_id, archived)
+}
+
+#[tauri::command]
+pub fn set_chat_tags_command(db: State<'_, Db>, chat_id: i64, tags: Vec<String>) -> Result<(), AppError> {
+    set_chat_tags(&*db.conn()?, chat_id, tags)
+}
+
+#[tauri::command]
+pub fn list_tags_command(db: State<'_, Db>) -> Result<Vec<TagCount>, AppError> {
+    list_tags(&*db.conn()?)
+}
@@ -0,0 +1,404 @@
+use rusqlite::{Connection, params};
+use serde::{Serialize, Deserialize};
+use std::time::Duration;
+use tauri::{AppHandle, Manager, State};
+use crate::db::db::Db;
+use crate::db::settings::{get_setting, set_setting};
+use crate::error::AppError;
+
+const POLICY_KEY: &str = "retention_policy";
+const RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const BYTES_PER_MB: i64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionAction {
+    Archive,
+    // Moves the chat to the trash, which purges it after the trash retention period
+    Trash,
+    // Deletes the chat permanently
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    // Whether the background task applies the policy
+    pub enabled: bool,
+    // Chats without activity for this many days get `action`
+    pub max_age_days: Option<u32>,
+    pub action: RetentionAction,
+    // Pinned chats are never archived or deleted
+    pub keep_pinned: bool,
+    // While the database is larger, the oldest chats are deleted permanently, trashed ones first
+    pub max_database_mb: Option<u64>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            enabled: false,
+            max_age_days: None,
+            action: RetentionAction::Archive,
+            keep_pinned: true,
+            max_database_mb: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionReason {
+    Age,
+    DatabaseSize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RetentionCandidate {
+    pub chat_id: i64,
+    pub name: String,
+    pub last_activity_at: String,
+    pub message_count: i64,
+    // Size of the chat's text and its full-text index, an estimate of the space deleting it frees
+    pub estimated_bytes: i64,
+    pub reason: RetentionReason,
+    pub action: RetentionAction,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RetentionReport {
+    // True when nothing was changed
+    pub dry_run: bool,
+    pub chats: Vec<RetentionCandidate>,
+    // Space used by the database before the policy is applied
+    pub database_bytes: i64,
+    // Estimated by a dry run, measured once the policy is applied
+    pub estimated_database_bytes_after: i64,
+}
+
+struct ChatUsage {
+    id: i64,
+    name: String,
+    last_activity_at: String,
+    pinned: bool,
+    archived: bool,
+    trashed: bool,
+    expired: bool,
+    message_count: i64,
+    estimated_bytes: i64,
+}
+
+fn validate_policy(policy: &RetentionPolicy) -> Result<(), AppError> {
+    if policy.max_age_days == Some(0) {
+        return Err(AppError::invalid_input("Maximum age must be at least 1 day"));
+    }
+    if policy.max_database_mb == Some(0) {
+        return Err(AppError::invalid_input("Maximum database size must be at least 1 MB"));
+    }
+    Ok(())
+}
+
+pub fn get_retention_policy(conn: &Connection) -> Result<RetentionPolicy, AppError> {
+    Ok(get_setting(conn, POLICY_KEY)?.unwrap_or_default())
+}
+
+pub fn set_retention_policy(conn: &Connection, policy: &RetentionPolicy) -> Result<(), AppError> {
+    validate_policy(policy)?;
+    set_setting(conn, POLICY_KEY, policy)
+}
+
+// Pages in use, without the free pages a VACUUM would give back.
+fn database_used_bytes(conn: &Connection) -> Result<i64, AppError> {
+    conn.query_row(
+        "SELECT (p.page_count - f.freelist_count) * s.page_size
+         FROM pragma_page_count() p, pragma_freelist_count() f, pragma_page_size() s",
+        [],
+        |row| row.get(0),
+    ).map_err(|e| AppError::with_cause("Failed to get database size", e))
+}
+
+// Every chat with what the policy needs to know about it, trashed chats first and
+// then by last activity, oldest first. Message content and attachment text are counted
+// twice, as the full-text index holds about as much again.
+fn chat_usage(conn: &Connection, max_age_days: Option<u32>) -> Result<Vec<ChatUsage>, AppError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT c.id, c.name, COALESCE(c.last_activity_at, c.created_at), c.pinned, c.archived,
+                c.deleted_at IS NOT NULL,
+                ?1 IS NOT NULL
+                    AND julianday(COALESCE(c.last_activity_at, c.created_at)) <= julianday('now') - ?1,
+                (SELECT COUNT(*) FROM messages m WHERE m.chat_id = c.id),
+                2 * (SELECT COALESCE(SUM(length(CAST(m.content AS BLOB))), 0)
+                     FROM messages m WHERE m.chat_id = c.id)
+                + (SELECT COALESCE(SUM(length(CAST(s.text AS BLOB))), 0)
+                   FROM message_sources s JOIN messages m ON m.id = s.message_id
+                   WHERE m.chat_id = c.id)
+                + (SELECT COALESCE(SUM(length(CAST(e.content AS BLOB))), 0)
+                   FROM message_edits e JOIN messages m ON m.id = e.message_id
+                   WHERE m.chat_id = c.id)
+                + (SELECT COALESCE(SUM(length(CAST(r.reason AS BLOB))), 0)
+                   FROM message_routing r JOIN messages m ON m.id = r.message_id
+                   WHERE m.chat_id = c.id)
+                + (SELECT COALESCE(SUM(length(CAST(g.unsupported_claims AS BLOB))), 0)
+                   FROM message_grounding g JOIN messages m ON m.id = g.message_id
+                   WHERE m.chat_id = c.id)
+                + 2 * (SELECT COALESCE(SUM(length(CAST(ch.text AS BLOB))), 0)
+                       FROM attachment_chunks ch JOIN attachments a ON a.id = ch.attachment_id
+                       WHERE a.chat_id = c.id)
+         FROM chats c
+         ORDER BY c.deleted_at IS NULL, COALESCE(c.last_activity_at, c.created_at), c.id"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let chats = stmt.query_map(params![max_age_days], |row| {
+        Ok(ChatUsage {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            last_activity_at: row.get(2)?,
+            pinned: row.get(3)?,
+            archived: row.get(4)?,
+            trashed: row.get(5)?,
+            expired: row.get::<_, Option<bool>>(6)?.unwrap_or(false),
+            message_count: row.get(7)?,
+            estimated_bytes: row.get(8)?,
+        })
+    }).map_err(|e| AppError::with_cause("Failed to query chats", e))?;
+
+    chats.collect::<Result<Vec<ChatUsage>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect chats", e))
+}
+
+fn candidate(chat: &ChatUsage, reason: RetentionReason, action: RetentionAction) -> RetentionCandidate {
+    RetentionCandidate {
+        chat_id: chat.id,
+        name: chat.name.clone(),
+        last_activity_at: chat.last_activity_at.clone(),
+        message_count: chat.message_count,
+        estimated_bytes: chat.estimated_bytes,
+        reason,
+        action,
+    }
+}
+
+fn max_bytes(max_mb: u64) -> i64 {
+    i64::try_from(max_mb).unwrap_or(i64::MAX / BYTES_PER_MB) * BYTES_PER_MB
+}
+
+// The chats the age rule applies to. The trash has its own retention period, so the
+// age rule leaves trashed chats alone.
+fn age_candidates(chats: &[ChatUsage], policy: &RetentionPolicy) -> Vec<RetentionCandidate> {
+    chats.iter()
+        .filter(|chat| chat.expired && !chat.trashed && !(policy.keep_pinned && chat.pinned))
+        .filter(|chat| !(policy.action == RetentionAction::Archive && chat.archived))
+        .map(|chat| candidate(chat, RetentionReason::Age, policy.action))
+        .collect()
+}
+
+// Marks `chat` for deletion by the size limit. False when it is kept or already deleted.
+fn add_size_candidate(candidates: &mut Vec<RetentionCandidate>, chat: &ChatUsage, policy: &RetentionPolicy) -> bool {
+    if policy.keep_pinned && chat.pinned {
+        return false;
+    }
+
+    match candidates.iter_mut().find(|c| c.chat_id == chat.id) {
+        Some(existing) if existing.action == RetentionAction::Delete => false,
+        Some(existing) => {
+            existing.reason = RetentionReason::DatabaseSize;
+            existing.action = RetentionAction::Delete;
+            true
+        }
+        None => {
+            candidates.push(candidate(chat, RetentionReason::DatabaseSize, RetentionAction::Delete));
+            true
+        }
+    }
+}
+
+// Space left once the deletions in `candidates` are done, going by their estimates.
+fn estimated_bytes_after(database_bytes: i64, candidates: &[RetentionCandidate]) -> i64 {
+    candidates.iter()
+        .filter(|c| c.action == RetentionAction::Delete)
+        .fold(database_bytes, |bytes, c| bytes - c.estimated_bytes)
+}
+
+fn apply_action(conn: &Connection, chat_id: i64, action: RetentionAction) -> Result<(), AppError> {
+    let sql = match action {
+        RetentionAction::Archive => "UPDATE chats SET archived = 1 WHERE id = ?1",
+        RetentionAction::Trash => {
+            "UPDATE chats SET deleted_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+             WHERE id = ?1 AND deleted_at IS NULL"
+        }
+        RetentionAction::Delete => "DELETE FROM chats WHERE id = ?1",
+    };
+    conn.execute(sql, [chat_id])
+        .map(|_| ())
+        .map_err(|e| AppError::with_cause(format!("Failed to apply retention to chat {}", chat_id), e))
+}
+
+// Works out what `policy` would do to the database as it is now, without changing it.
+pub fn preview_retention(conn: &Connection, policy: &RetentionPolicy) -> Result<RetentionReport, AppError> {
+    validate_policy(policy)?;
+
+    let chats = chat_usage(conn, policy.max_age_days)?;
+    let database_bytes = database_used_bytes(conn)?;
+    let mut candidates = age_candidates(&chats, policy);
+    let mut remaining_bytes = estimated_bytes_after(database_bytes, &candidates);
+
+    if let Some(max_mb) = policy.max_database_mb {
+        let max_bytes = max_bytes(max_mb);
+
+        for chat in &chats {
+            if remaining_bytes <= max_bytes {
+                break;
+            }
+            if add_size_candidate(&mut candidates, chat, policy) {
+                remaining_bytes -= chat.estimated_bytes;
+            }
+        }
+    }
+
+    Ok(RetentionReport {
+        dry_run: true,
+        chats: candidates,
+        database_bytes,
+        estimated_database_bytes_after: remaining_bytes.max(0),
+    })
+}
+
+// Deleted rows stay in the full-text indexes until their segments are merged, so those are
+// optimized before the VACUUM.
+fn compact(conn: &Connection) -> Result<(), AppError> {
+    conn.execute_batch(
+        "INSERT INTO messages_fts (messages_fts) VALUES ('optimize');
+         INSERT INTO chats_fts (chats_fts) VALUES ('optimize');
+         INSERT INTO attachment_chunks_fts (attachment_chunks_fts) VALUES ('optimize');
+         VACUUM;"
+    ).map_err(|e| AppError::with_cause("Failed to compact database", e))
+}
+
+// Applies `policy` and reports what it changed. The size limit deletes one chat at a time
+// and measures the database again after each, so it stops as soon as the database fits.
+// Space it frees is returned to the file system with a VACUUM.
+pub fn apply_retention(conn: &Connection, policy: &RetentionPolicy) -> Result<RetentionReport, AppError> {
+    validate_policy(policy)?;
+
+    let chats = chat_usage(conn, policy.max_age_days)?;
+    let database_bytes = database_used_bytes(conn)?;
+    let mut candidates = age_candidates(&chats, policy);
+
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| AppError::with_cause("Failed to start transaction", e))?;
+    for chat in &candidates {
+        apply_action(&tx, chat.chat_id, chat.action)?;
+    }
+    tx.commit()
+        .map_err(|e| AppError::with_cause("Failed to commit retention", e))?;
+
+    if let Some(max_mb) = policy.max_database_mb {
+        let max_bytes = max_bytes(max_mb);
+        let mut deleted = candidates.iter().any(|chat| chat.action == RetentionAction::Delete);
+
+        // Pages only count as free once all their rows are gone, so the measurement can stay
+        // high until the VACUUM. The estimate ends a round in that case, and the next round
+        // starts from the compacted size.
+        loop {
+            if deleted {
+                compact(conn)?;
+            }
+            let mut remaining_bytes = database_used_bytes(conn)?;
+            if remaining_bytes <= max_bytes {
+                break;
+            }
+
+            let tx = conn.unchecked_transaction()
+                .map_err(|e| AppError::with_cause("Failed to start transaction", e))?;
+            deleted = false;
+            for chat in &chats {
+                if remaining_bytes <= max_bytes || database_used_bytes(&tx)? <= max_bytes {
+                    break;
+                }
+                if add_size_candidate(&mut candidates, chat, policy) {
+                    apply_action(&tx, chat.id, RetentionAction::Delete)?;
+                    remaining_bytes -= chat.estimated_bytes;
+                    deleted = true;
+                }
+            }
+            tx.commit()
+                .map_err(|e| AppError::with_cause("Failed to commit retention", e))?;
+
+            // Nothing left that the policy may delete
+            if !deleted {
+                break;
+            }
+        }
+    }
+
+    Ok(RetentionReport {
+        dry_run: false,
+        chats: candidates,
+        database_bytes,
+        estimated_database_bytes_after: database_used_bytes(conn)?,
+    })
+}
+
+fn run_scheduled_retention(db: &Db) -> Result<(), AppError> {
+    // Waits for the passphrase of an encrypted database
+    if db.is_locked()? {
+        return Ok(());
+    }
+
+    let conn = db.conn()?;
+    let policy = get_retention_policy(&conn)?;
+    if !policy.enabled {
+        return Ok(());
+    }
+
+    let report = apply_retention(&conn, &policy)?;
+    if !report.chats.is_empty() {
+        log::info!("Retention policy applied to {} chats", report.chats.len());
+    }
+
+    Ok(())
+}
+
+// Applies the saved policy periodically. Called once from setup.
+pub fn start_retention_scheduler(app: AppHandle) {
+    std::thread::spawn(move || loop {
+        if let Err(e) = run_scheduled_retention(&app.state::<Db>()) {
+            log::warn!("Applying retention policy failed: {}", e);
+        }
+        std::thread::sleep(RETENTION_CHECK_INTERVAL);
+    });
+}
+
+#[tauri::command]
+pub fn get_retention_policy_command(db: State<'_, Db>) -> Result<RetentionPolicy, AppError> {
+    get_retention_policy(&*db.conn()?)
+}
+
+#[tauri::command]
+pub fn set_retention_policy_command(db: State<'_, Db>, policy: RetentionPolicy) -> Result<(), AppError> {
+    set_retention_policy(&*db.conn()?, &policy)
+}
+
+// Dry run of `policy`, or of the saved policy when none is given.
+#[tauri::command]
+pub fn preview_retention_command(db: State<'_, Db>, policy: Option<RetentionPolicy>) -> Result<RetentionReport, AppError> {
+    let conn = db.conn()?;
+    let policy = match policy {
+        Some(policy) => policy,
+        None => get_retention_policy(&conn)?,
+    };
+    preview_retention(&conn, &policy)
+}
+
+// Applies the saved policy now, whether or not the background task is enabled.
+#[tauri::command]
+pub async fn apply_retention_command(app: AppHandle) -> Result<RetentionReport, AppError> {
+    tokio::task::spawn_blocking(move || {
+        let db = app.state::<Db>();
+        let conn = db.conn()?;
+        let policy = get_retention_policy(&conn)?;
+        apply_retention(&conn, &policy)
+    })
+        .await
+        .map_err(|e| AppError::with_cause("Applying retention policy failed", e))?
+}
@@ -0,0 +1,219 @@
+use rusqlite::{Connection, params};
+use tauri::State;
+use crate::db::db::Db;
+use crate::db::export::escape_html;
+use crate::error::AppError;
+
+const DEFAULT_SEARCH_LIMIT: u32 = 50;
+// Private use characters FTS5 puts around matches, swapped for the tags once the text is escaped
+const MATCH_START: &str = "\u{E000}";
+const MATCH_END: &str = "\u{E001}";
+const HIGHLIGHT_START: &str = "<mark>";
+const HIGHLIGHT_END: &str = "</mark>";
+const SNIPPET_ELLIPSIS: &str = "…";
+// Number of tokens shown around the match in a snippet
+const SNIPPET_TOKENS: i64 = 16;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct SearchHit {
+    pub chat_id: i64,
+    pub chat_name: String,
+    // None when the hit is on the chat name rather than a message
+    pub message_id: Option<i64>,
+    pub is_user: Option<bool>,
+    // HTML escaped, with matches wrapped in <mark>
+    pub snippet: String,
+    pub timestamp: String,
+    // bm25 score, lower is more relevant
+    pub rank: f64,
+}
+
+// Turns user input into a safe FTS5 query.
+// "quoted text" becomes a phrase, a trailing * makes a prefix query and every
+// other word must appear in the result. FTS5 operators typed by the user are
+// treated as plain words.
+pub fn build_fts_query(input: &str) -> Option<String> {
+    let mut terms = Vec::new();
+
+    for (i, part) in input.split('"').enumerate() {
+        // Odd parts sit between a pair of quotes
+        if i % 2 == 1 {
+            let phrase = part.split_whitespace().collect::<Vec<_>>().join(" ");
+            if !phrase.is_empty() {
+                terms.push(format!("\"{}\"", phrase));
+            }
+            continue;
+        }
+
+        for word in part.split_whitespace() {
+            let is_prefix = word.ends_with('*');
+            let word = word.trim_end_matches('*');
+            if word.is_empty() {
+                continue;
+            }
+            if is_prefix {
+                terms.push(format!("\"{}\"*", word));
+            } else {
+                terms.push(format!("\"{}\"", word));
+            }
+        }
+    }
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+fn highlight_html(snippet: &str) -> String {
+    escape_html(snippet)
+        .replace(MATCH_START, HIGHLIGHT_START)
+        .replace(MATCH_END, HIGHLIGHT_END)
+}
+
+pub fn search_index(conn: &Connection, query: &str, limit: u32) -> Result<Vec<SearchHit>, AppError> {
+    let fts_query = match build_fts_query(query) {
+        Some(fts_query) => fts_query,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT m.chat_id, c.name, m.id, m.is_user,
+                snippet(messages_fts, 0, ?2, ?3, ?4, ?5),
+                m.timestamp, bm25(messages_fts)
+         FROM messages_fts
+         JOIN messages m ON m.id = messages_fts.rowid
+         JOIN chats c ON c.id = m.chat_id
+         WHERE messages_fts MATCH ?1 AND c.deleted_at IS NULL
+         ORDER BY bm25(messages_fts)
+         LIMIT ?6"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let message_hits = stmt.query_map(
+        params![fts_query, MATCH_START, MATCH_END, SNIPPET_ELLIPSIS, SNIPPET_TOKENS, limit],
+        |row| {
+            Ok(SearchHit {
+                chat_id: row.get(0)?,
+                chat_name: row.get(1)?,
+                message_id: Some(row.get(2)?),
+                is_user: Some(row.get(3)?),
+                snippet: highlight_html(&row.get::<_, String>(4)?),
+                timestamp: row.get(5)?,
+                rank: row.get(6)?,
+            })
+        },
+    ).map_err(|e| AppError::with_cause("Failed to search messages", e))?;
+
+    let mut hits = message_hits.collect::<Result<Vec<SearchHit>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect search results", e))?;
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT c.id, c.name, highlight(chats_fts, 0, ?2, ?3), c.created_at, bm25(chats_fts)
+         FROM chats_fts
+         JOIN chats c ON c.id = chats_fts.rowid
+         WHERE chats_fts MATCH ?1 AND c.deleted_at IS NULL
+         ORDER BY bm25(chats_fts)
+         LIMIT ?4"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let chat_hits = stmt.query_map(
+        params![fts_query, MATCH_START, MATCH_END, limit],
+        |row| {
+            Ok(SearchHit {
+                chat_id: row.get(0)?,
+                chat_name: row.get(1)?,
+                message_id: None,
+                is_user: None,
+                snippet: highlight_html(&row.get::<_, String>(2)?),
+                timestamp: row.get(3)?,
+                rank: row.get(4)?,
+            })
+        },
+    ).map_err(|e| AppError::with_cause("Failed to search chats", e))?;
+
+    for hit in chat_hits {
+        hits.push(hit.map_err(|e| AppError::with_cause("Failed to collect search results", e))?);
+    }
+
+    hits.sort_by(|a, b| a.rank.partial_cmp(&b.rank).unwrap_or(std::cmp::Ordering::Equal));
+    hits.truncate(limit as usize);
+
+    Ok(hits)
+}
+
+#[tauri::command]
+pub fn search_messages(db: State<'_, Db>, query: String, limit: Option<u32>) -> Result<Vec<SearchHit>, AppError> {
+    search_index(&*db.conn()?, &query, limit.unwrap_or(DEFAULT_SEARCH_LIMIT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::db::test_support::{chat_with, memory_db};
+
+    #[test]
+    fn builds_queries_from_words_phrases_and_prefixes() {
+        assert_eq!(build_fts_query("rust borrow").as_deref(), Some("\"rust\" \"borrow\""));
+        assert_eq!(build_fts_query("\"borrow  checker\" rules").as_deref(), Some("\"borrow checker\" \"rules\""));
+        assert_eq!(build_fts_query("borr*").as_deref(), Some("\"borr\"*"));
+        // An unclosed quote still makes a phrase of the rest
+        assert_eq!(build_fts_query("say \"hello world").as_deref(), Some("\"say\" \"hello world\""));
+    }
+
+    #[test]
+    fn fts_operators_are_plain_words() {
+        assert_eq!(build_fts_query("cats OR dogs").as_deref(), Some("\"cats\" \"OR\" \"dogs\""));
+        assert_eq!(build_fts_query("NEAR(a b) -x ^y").as_deref(), Some("\"NEAR(a\" \"b)\" \"-x\" \"^y\""));
+    }
+
+    #[test]
+    fn empty_input_gives_no_query() {
+        for input in ["", "   ", "\"\"", "* **", "\" \""] {
+            assert_eq!(build_fts_query(input), None, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn odd_input_does_not_break_the_search() {
+        let conn = memory_db();
+        chat_with(&conn, &["hello world"]);
+
+        for input in ["cats OR", "NEAR(", "a:b", "(", "*hello", "col:hello", "\"unclosed"] {
+            assert!(search_index(&conn, input, 10).is_ok(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn snippets_are_escaped_and_highlighted() {
+        let conn = memory_db();
+        chat_with(&conn, &["say hello <img src=x onerror=alert(1)> & \"bye\""]);
+
+        let hits = search_index(&conn, "hello", 10).unwrap();
+        let message_hit = hits.iter().find(|hit| hit.message_id.is_some()).unwrap();
+        assert_eq!(
+            message_hit.snippet,
+            "say <mark>hello</mark> &lt;img src=x onerror=alert(1)&gt; &amp; &quot;bye&quot;"
+        );
+    }
+
+    #[test]
+    fn chat_names_are_escaped_too() {
+        let conn = memory_db();
+        let (chat_id, _) = chat_with(&conn, &["unrelated"]);
+        crate::db::db::rename_chat(&conn, chat_id, "<b>Plans</b> & notes".to_string(), true).unwrap();
+
+        let hits = search_index(&conn, "plans", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message_id, None);
+        assert_eq!(hits[0].snippet, "&lt;b&gt;<mark>Plans</mark>&lt;/b&gt; &amp; notes");
+    }
+
+    #[test]
+    fn highlight_only_replaces_the_markers() {
+        assert_eq!(
+            highlight_html(&format!("a {}<b>{} c", MATCH_START, MATCH_END)),
+            "a <mark>&lt;b&gt;</mark> c"
+        );
+    }
+}
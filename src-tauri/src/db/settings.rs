@@ -0,0 +1,37 @@
+use rusqlite::{Connection, params};
+use serde::{de::DeserializeOwned, Serialize};
+use crate::error::AppError;
+
+// App settings kept in chats.db as JSON values, keyed by name.
+// Missing keys fall back to the defaults chosen by each caller.
+
+pub fn get_setting<T: DeserializeOwned>(conn: &Connection, key: &str) -> Result<Option<T>, AppError> {
+    let mut stmt = conn.prepare_cached("SELECT value FROM app_settings WHERE key = ?1")
+        .map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let value: Option<String> = match stmt.query_row([key], |row| row.get(0)) {
+        Ok(value) => Some(value),
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(AppError::with_cause(format!("Failed to read setting {}", key), e)),
+    };
+
+    value
+        .map(|value| serde_json::from_str(&value))
+        .transpose()
+        .map_err(|e| AppError::with_cause(format!("Invalid value for setting {}", key), e))
+}
+
+pub fn set_setting<T: Serialize>(conn: &Connection, key: &str, value: &T) -> Result<(), AppError> {
+    let value = serde_json::to_string(value)
+        .map_err(|e| AppError::with_cause(format!("Failed to serialize setting {}", key), e))?;
+
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO app_settings (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    stmt.execute(params![key, value])
+        .map_err(|e| AppError::with_cause(format!("Failed to save setting {}", key), e))?;
+
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use rusqlite::Connection;
+use tauri::{AppHandle, Manager, State};
+use crate::config::llama_cli;
+use crate::db::chat_settings::get_chat_settings;
+use crate::db::db::{get_chat_messages, rename_chat, Db, Message, MessageRole};
+use crate::db::settings::{get_setting, set_setting};
+use crate::error::{AppError, ErrorCode};
+
+const AUTO_TITLE_KEY: &str = "auto_title_enabled";
+const DEFAULT_AUTO_TITLE: bool = true;
+const TITLE_MAX_TOKENS: u32 = 24;
+const TITLE_MAX_CHARS: usize = 60;
+// Characters of each message shown to the model when asking for a title
+const TITLE_CONTEXT_CHARS: usize = 1000;
+
+pub fn is_auto_title_enabled(conn: &Connection) -> Result<bool, AppError> {
+    Ok(get_setting(conn, AUTO_TITLE_KEY)?.unwrap_or(DEFAULT_AUTO_TITLE))
+}
+
+fn name_set_by_user(conn: &Connection, chat_id: i64) -> Result<bool, AppError> {
+    conn.query_row("SELECT name_set_by_user FROM chats WHERE id = ?1", [chat_id], |row| row.get(0))
+        .map_err(|e| AppError::with_cause("Failed to get chat", e))
+}
+
+fn title_prompt(exchange: &[Message]) -> String {
+    let conversation: String = exchange.iter()
+        .map(|message| {
+            let speaker = if message.is_user { "User" } else { "Assistant" };
+            let content: String = message.content.trim().chars().take(TITLE_CONTEXT_CHARS).collect();
+            format!("{}: {}\n", speaker, content)
+        })
+        .collect();
+
+    format!(
+        "Write a short title of at most six words for the following conversation. \
+         Reply with the title only, without quotes.\n\n{}\nTitle:",
+        conversation
+    )
+}
+
+// Takes the first line of the model's answer and strips quotes, labels and trailing punctuation.
+fn clean_title(raw: &str) -> Option<String> {
+    let line = raw.lines().map(str::trim).find(|line| !line.is_empty())?;
+    let line = line.strip_prefix("Title:").unwrap_or(line);
+    let title = line
+        .trim_matches(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '*' | '#' | '`'))
+        .trim_end_matches(['.', ':', ';', ','])
+        .trim();
+
+    if title.is_empty() {
+        return None;
+    }
+
+    let title: String = title.chars().take(TITLE_MAX_CHARS).collect();
+    Some(title.trim_end().to_string())
+}
+
+// The first user message and reply when `message` completes the chat's first exchange,
+// otherwise None.
+fn first_exchange(conn: &Connection, chat_id: i64, message: &Message) -> Result<Option<Vec<Message>>, AppError> {
+    if message.is_user || !is_auto_title_enabled(conn)? || name_set_by_user(conn, chat_id)? {
+        return Ok(None);
+    }
+
+    let messages = get_chat_messages(conn, chat_id)?;
+    let conversation: Vec<Message> = messages.into_iter()
+        .filter(|message| message.role != Some(MessageRole::System))
+        .collect();
+
+    match conversation.as_slice() {
+        [question, answer] if question.is_user && !answer.is_user => Ok(Some(conversation)),
+        _ => Ok(None),
+    }
+}
+
+// Asks the model for a title after the first exchange of a chat, without blocking the caller.
+// The model is the one that wrote the answer, or the chat's model when that was not recorded.
+// The title is skipped rather than loading the model again while another completion runs.
+pub fn spawn_title_generation(app: &AppHandle, conn: &Connection, chat_id: i64, message: &Message) -> Result<(), AppError> {
+    let exchange = match first_exchange(conn, chat_id, message)? {
+        Some(exchange) => exchange,
+        None => return Ok(()),
+    };
+
+    let model = match message.model.clone().or(get_chat_settings(conn, chat_id)?.model) {
+        Some(model) => model,
+        None => {
+            log::info!("Not generating a title for chat {}: no model recorded", chat_id);
+            return Ok(());
+        }
+    };
+
+    let app = app.clone();
+    std::thread::spawn(move || {
+        let title = match llama_cli::complete_if_idle(&model, &title_prompt(&exchange), TITLE_MAX_TOKENS) {
+            Ok(Some(raw)) => clean_title(&raw).ok_or_else(|| AppError::new(ErrorCode::Model, "Model returned an empty title")),
+            Ok(None) => {
+                log::info!("Not generating a title for chat {}: the model is busy", chat_id);
+                return;
+            }
+            Err(e) => Err(e),
+        };
+
+        let result = title.and_then(|title| {
+            let db = app.state::<Db>();
+            let conn = db.conn()?;
+            rename_chat(&conn, chat_id, title, false)
+        });
+
+        match result {
+            Ok(true) => log::info!("Generated title for chat {}", chat_id),
+            // Renamed by the user while the title was being generated
+            Ok(false) => {}
+            Err(e) => log::warn!("Failed to generate title for chat {}: {}", chat_id, e),
+        }
+    });
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_auto_title_enabled_command(db: State<'_, Db>) -> Result<bool, AppError> {
+    is_auto_title_enabled(&*db.conn()?)
+}
+
+#[tauri::command]
+pub fn set_auto_title_enabled_command(db: State<'_, Db>, enabled: bool) -> Result<(), AppError> {
+    set_setting(&*db.conn()?, AUTO_TITLE_KEY, &enabled)
+}
@@ -0,0 +1,148 @@
+use rusqlite::{Connection, params};
+use tauri::State;
+use crate::db::db::Db;
+use crate::db::settings::{get_setting, set_setting};
+use crate::error::AppError;
+
+const TRASH_RETENTION_KEY: &str = "trash_retention_days";
+// Days a deleted chat stays in the trash, 0 keeps it until the trash is emptied
+const DEFAULT_TRASH_RETENTION_DAYS: u32 = 30;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct TrashedChat {
+    pub id: i64,
+    pub name: String,
+    pub created_at: String,
+    pub deleted_at: String,
+    pub message_count: i64,
+}
+
+pub fn list_trash(conn: &Connection) -> Result<Vec<TrashedChat>, AppError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT c.id, c.name, c.created_at, c.deleted_at,
+                (SELECT COUNT(*) FROM messages m WHERE m.chat_id = c.id)
+         FROM chats c
+         WHERE c.deleted_at IS NOT NULL
+         ORDER BY c.deleted_at DESC"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let chats = stmt.query_map([], |row| {
+        Ok(TrashedChat {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            created_at: row.get(2)?,
+            deleted_at: row.get(3)?,
+            message_count: row.get(4)?,
+        })
+    }).map_err(|e| AppError::with_cause("Failed to query trash", e))?;
+
+    chats.collect::<Result<Vec<TrashedChat>, _>>()
+        .map_err(|e| AppError::with_cause("Failed to collect trash", e))
+}
+
+pub fn restore_chat(conn: &Connection, chat_id: i64) -> Result<(), AppError> {
+    let mut stmt = conn.prepare_cached(
+        "UPDATE chats SET deleted_at = NULL WHERE id = ?1 AND deleted_at IS NOT NULL"
+    ).map_err(|e| AppError::with_cause("Failed to prepare statement", e))?;
+
+    let restored = stmt.execute([chat_id])
+        .map_err(|e| AppError::with_cause("Failed to restore chat", e))?;
+
+    if restored == 0 {
+        return Err(AppError::not_found(format!("Chat {} is not in the trash", chat_id)));
+    }
+
+    Ok(())
+}
+
+// Permanently deletes a trashed chat. Messages, sources, grounding and routing
+// rows are removed by ON DELETE CASCADE.
+pub fn purge_chat(conn: &Connection, chat_id: i64) -> Result<(), AppError> {
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| AppError::with_cause("Failed to start transaction", e))?;
+
+    let purged = tx.execute("DELETE FROM chats WHERE id = ?1 AND deleted_at IS NOT NULL", [chat_id])
+        .map_err(|e| AppError::with_cause("Failed to delete chat", e))?;
+
+    if purged == 0 {
+        return Err(AppError::not_found(format!("Chat {} is not in the trash", chat_id)));
+    }
+
+    tx.commit()
+        .map_err(|e| AppError::with_cause("Failed to commit delete", e))?;
+
+    Ok(())
+}
+
+pub fn empty_trash(conn: &Connection) -> Result<usize, AppError> {
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| AppError::with_cause("Failed to start transaction", e))?;
+
+    let purged = tx.execute("DELETE FROM chats WHERE deleted_at IS NOT NULL", [])
+        .map_err(|e| AppError::with_cause("Failed to empty trash", e))?;
+
+    tx.commit()
+        .map_err(|e| AppError::with_cause("Failed to commit delete", e))?;
+
+    Ok(purged)
+}
+
+pub fn get_trash_retention_days(conn: &Connection) -> Result<u32, AppError> {
+    Ok(get_setting(conn, TRASH_RETENTION_KEY)?.unwrap_or(DEFAULT_TRASH_RETENTION_DAYS))
+}
+
+// Deletes chats that have been in the trash longer than the retention period.
+pub fn purge_expired_trash(conn: &Connection) -> Result<usize, AppError> {
+    let days = get_trash_retention_days(conn)?;
+    if days == 0 {
+        return Ok(0);
+    }
+
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| AppError::with_cause("Failed to start transaction", e))?;
+
+    let purged = tx.execute(
+        "DELETE FROM chats
+         WHERE deleted_at IS NOT NULL
+           AND julianday(deleted_at) <= julianday('now') - ?1",
+        params![days],
+    ).map_err(|e| AppError::with_cause("Failed to purge trash", e))?;
+
+    tx.commit()
+        .map_err(|e| AppError::with_cause("Failed to commit delete", e))?;
+
+    Ok(purged)
+}
+
+#[tauri::command]
+pub fn list_trash_command(db: State<'_, Db>) -> Result<Vec<TrashedChat>, AppError> {
+    list_trash(&*db.conn()?)
+}
+
+#[tauri::command]
+pub fn restore_chat_command(db: State<'_, Db>, chat_id: i64) -> Result<(), AppError> {
+    restore_chat(&*db.conn()?, chat_id)
+}
+
+#[tauri::command]
+pub fn purge_chat_command(db: State<'_, Db>, chat_id: i64) -> Result<(), AppError> {
+    purge_chat(&*db.conn()?, chat_id)
+}
+
+#[tauri::command]
+pub fn empty_trash_command(db: State<'_, Db>) -> Result<usize, AppError> {
+    empty_trash(&*db.conn()?)
+}
+
+#[tauri::command]
+pub fn get_trash_retention_days_command(db: State<'_, Db>) -> Result<u32, AppError> {
+    get_trash_retention_days(&*db.conn()?)
+}
+
+#[tauri::command]
+pub fn set_trash_retention_days_command(db: State<'_, Db>, days: u32) -> Result<(), AppError> {
+    let conn = db.conn()?;
+    set_setting(&conn, TRASH_RETENTION_KEY, &days)?;
+    purge_expired_trash(&conn)?;
+    Ok(())
+}
@@ -0,0 +1,165 @@
+use serde::{Serialize, Deserialize};
+use std::fmt;
+
+// Stable identifiers the frontend can match on. Codes are part of the command API:
+// add new ones as needed, but never rename or reuse an existing one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    NotFound,
+    AlreadyExists,
+    InvalidInput,
+    Cancelled,
+    // Another download, backup or restore is already running
+    Busy,
+    // SQLite could not get a lock because another connection is writing
+    DatabaseBusy,
+    // The database is encrypted and waiting for its passphrase
+    DatabaseLocked,
+    WrongPassphrase,
+    Database,
+    Io,
+    Network,
+    // llama.cpp failed to start or run
+    Model,
+    Internal,
+}
+
+// Error returned by every command. Serialized as { "code", "message", "details" }, where
+// message is meant for display and details holds the underlying error, when there is one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Option<String>,
+}
+
+impl AppError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        AppError {
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    // Wraps a lower-level error. The code follows from the kind of error, see ErrorCause.
+    pub fn with_cause(message: impl Into<String>, cause: impl ErrorCause) -> Self {
+        AppError {
+            code: cause.code(),
+            message: message.into(),
+            details: Some(cause.to_string()),
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn already_exists(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::AlreadyExists, message)
+    }
+
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidInput, message)
+    }
+
+    pub fn cancelled(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Cancelled, message)
+    }
+
+    pub fn busy(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Busy, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.details {
+            Some(details) => write!(f, "{}: {}", self.message, details),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+// Errors from libraries and the standard library that AppError::with_cause accepts,
+// with the code each one maps to.
+pub trait ErrorCause: fmt::Display {
+    fn code(&self) -> ErrorCode;
+}
+
+impl ErrorCause for AppError {
+    fn code(&self) -> ErrorCode {
+        self.code
+    }
+}
+
+impl ErrorCause for rusqlite::Error {
+    fn code(&self) -> ErrorCode {
+        match self.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked) => ErrorCode::DatabaseBusy,
+            Some(rusqlite::ErrorCode::ConstraintViolation) => ErrorCode::InvalidInput,
+            _ => match self {
+                rusqlite::Error::QueryReturnedNoRows => ErrorCode::NotFound,
+                _ => ErrorCode::Database,
+            },
+        }
+    }
+}
+
+impl ErrorCause for std::io::Error {
+    fn code(&self) -> ErrorCode {
+        match self.kind() {
+            std::io::ErrorKind::NotFound => ErrorCode::NotFound,
+            std::io::ErrorKind::AlreadyExists => ErrorCode::AlreadyExists,
+            _ => ErrorCode::Io,
+        }
+    }
+}
+
+impl ErrorCause for serde_json::Error {
+    fn code(&self) -> ErrorCode {
+        match self.classify() {
+            serde_json::error::Category::Io => ErrorCode::Io,
+            _ => ErrorCode::InvalidInput,
+        }
+    }
+}
+
+impl ErrorCause for reqwest::Error {
+    fn code(&self) -> ErrorCode {
+        ErrorCode::Network
+    }
+}
+
+impl ErrorCause for zip::result::ZipError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            zip::result::ZipError::Io(_) => ErrorCode::Io,
+            zip::result::ZipError::FileNotFound => ErrorCode::NotFound,
+            _ => ErrorCode::InvalidInput,
+        }
+    }
+}
+
+impl ErrorCause for Box<dyn std::error::Error> {
+    fn code(&self) -> ErrorCode {
+        ErrorCode::Internal
+    }
+}
+
+impl ErrorCause for tokio::task::JoinError {
+    fn code(&self) -> ErrorCode {
+        if self.is_cancelled() {
+            ErrorCode::Cancelled
+        } else {
+            ErrorCode::Internal
+        }
+    }
+}
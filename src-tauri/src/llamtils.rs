@@ -6,13 +6,16 @@ use rand::Rng;
 use serde::{Serialize, Deserialize};
 use tauri::api::path;
 use std::error::Error;
+use crate::db::chat_settings::{RagOptions, DEFAULT_RAG_TOP_N};
+use crate::rag::grounding::RetrievedChunk;
+use crate::rag::injection::{format_context, prepare_sources, SuspiciousChunkAction, UNTRUSTED_CONTEXT_NOTICE};
 
 const EMBEDDING_DIM: usize = 384;
 
 
 pub fn setup_levchat_dirs() -> Result<(), Box<dyn Error>> {
     let doc_dir = path::document_dir()
-        .ok_or_else(|| "Failed to get documents directory".to_string())?;
+        .ok_or_else(|| AppError::internal("Failed to get documents directory"))?;
  
     let levchat_dir = doc_dir.join("LevChat");
     let data_dir = levchat_dir.join("data");
@@ -21,19 +24,19 @@ pub fn setup_levchat_dirs() -> Result<(), Box<dyn Error>> {
     if !levchat_dir.exists() {
         println!("Creating LevChat directory...");
         fs::create_dir_all(&levchat_dir)
-            .map_err(|e| format!("Failed to create LevChat directory: {}", e))?;
+            .map_err(|e| AppError::with_cause("Failed to create LevChat directory", e))?;
     }
  
     if !data_dir.exists() {
         println!("Creating data directory...");
         fs::create_dir_all(&data_dir)
-            .map_err(|e| format!("Failed to create data directory: {}", e))?;
+            .map_err(|e| AppError::with_cause("Failed to create data directory", e))?;
     }
  
     if !model_dir.exists() {
         println!("Creating model directory...");
         fs::create_dir_all(&model_dir)
-            .map_err(|e| format!("Failed to create model directory: {}", e))?;
+            .map_err(|e| AppError::with_cause("Failed to create model directory", e))?;
     }
 
     // println!("\nLevChat directory structure:");
@@ -63,6 +66,7 @@ pub struct RAGProcessor {
     overlap: usize,
     documents: Vec<Document>,
     data_dir: PathBuf,
+    injection_action: SuspiciousChunkAction,
 }
 
 impl RAGProcessor {
@@ -80,6 +84,7 @@ impl RAGProcessor {
             overlap,
             documents: Vec::new(),
             data_dir,
+            injection_action: SuspiciousChunkAction::default(),
         };
 
         processor.process_documents()?;
@@ -87,6 +92,16 @@ impl RAGProcessor {
         Ok(processor)
     }
 
+    pub fn set_injection_action(&mut self, action: SuspiciousChunkAction) {
+        self.injection_action = action;
+    }
+
+    // Applies a chat's RAG settings and returns how many chunks to retrieve for it.
+    pub fn apply_rag_options(&mut self, options: &RagOptions) -> usize {
+        self.injection_action = options.injection_action;
+        options.top_n.unwrap_or(DEFAULT_RAG_TOP_N)
+    }
+
     fn generate_embedding(&self) -> Vec<f32> {
         let mut rng = rand::thread_rng();
         (0..EMBEDDING_DIM).map(|_| rng.gen::<f32>()).collect()
@@ -193,39 +208,41 @@ impl RAGProcessor {
     }
 
     pub fn generate_rag_prompt(&self, query: &str, top_n: usize) -> String {
+        self.generate_rag_prompt_with_sources(query, top_n).0
+    }
+
+    // Same as generate_rag_prompt, but also returns the chunks pasted into the prompt
+    // so the answer can later be checked against them.
+    pub fn generate_rag_prompt_with_sources(&self, query: &str, top_n: usize) -> (String, Vec<RetrievedChunk>) {
         let contexts = self.retrieve_context(query, top_n);
-        let context_str = contexts.iter()
-            .map(|(text, filename)| format!("From {}: {}", filename, text))
-            .collect::<Vec<_>>()
-            .join("\n\n");
-        
-        format!(
-            r#"Take your time and analyse the following context:
+        let sources = prepare_sources(contexts, self.injection_action);
+        let context_str = format_context(&sources);
+
+        let prompt = format!(
+            r#"Take your time and analyse the following context.
+{}
+
 {}
 
 Now use the context to answer the following query: {}
 PS: (End your response with [done])
 Your response: "#,
+            UNTRUSTED_CONTEXT_NOTICE,
             context_str,
             query
-        )
+        );
+
+        (prompt, sources)
     }
 }
 
 use std::env;
 use std::fs;
-use std::fs::File;
-use std::io::Write;
 use std::path::PathBuf;
 use std::process::Command;
-use reqwest;
 use sysinfo::System;
-use reqwest::Client;
-use std::sync::{Arc, Mutex};
-use futures::StreamExt;
-use tokio::sync::mpsc;
 use tauri::api::path;
-use lazy_static::lazy_static;
+use crate::error::AppError;
 
 const GITHUB_RELEASES_URL: &str = "https://github.com/ggerganov/llama.cpp/releases/download";
 const LATEST_VERSION: &str = "b4164";
@@ -362,7 +379,7 @@ pub fn install_llama_cpp() -> Result<(), Box<dyn std::error::Error>> {
 
     let platform = detect_platform();
     let doc_dir = path::document_dir()
-        .ok_or_else(|| "Failed to get documents directory".to_string())?;
+        .ok_or_else(|| AppError::internal("Failed to get documents directory"))?;
  
     let install_dir = doc_dir.join("LevChat");
     
@@ -399,180 +416,24 @@ pub fn install_llama_cpp() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 #[tauri::command]
-pub fn install_llama_cpp_command() -> Result<(), String> {
-    install_llama_cpp().map_err(|e| e.to_string())
-}
-lazy_static! {
-    static ref LLAMACPP_STATE: Arc<Mutex<DownloadState>> = Arc::new(Mutex::new(DownloadState::default()));
-}
-
-#[derive(Default)]
-struct DownloadState {
-    total_size: Option<u64>,
-    downloaded_size: u64,
-    is_downloading: bool,
-    filename: Option<String>,
-    cancel_tx: Option<mpsc::Sender<()>>,
-}
-lazy_static! {
-    static ref DOWNLOAD_STATE: Arc<Mutex<DownloadState>> = Arc::new(Mutex::new(DownloadState::default()));
-}
-
-
-#[tauri::command]
-pub async fn download_model(
-    url: String, 
-    model_type: String
-) -> Result<String, String> {
-    // Prepare download path
-    let doc_dir = path::document_dir()
-        .ok_or_else(|| "Failed to get documents directory".to_string())?;
- 
-    let levchat_dir = doc_dir.join("LevChat");
-    let download_path = match model_type.as_str() {
-        "languageModel" => levchat_dir.join("model"),
-        "embeddingModel" => levchat_dir.join("em_model"),
-        _ => return Err("Invalid model type".to_string())
-    };
-
-    let filename = url.split('/').last()
-        .ok_or_else(|| "Could not extract filename from URL".to_string())?
-        .to_string();
-    let file_path = download_path.join(&filename);
-
-    if file_path.exists() {
-        return Err(format!("Model {} already exists", filename));
-    }
-
-    // Create channels for cancellation
-    let (cancel_tx, mut cancel_rx) = mpsc::channel(1);
-
-    // Update global state
-    {
-        let mut state = DOWNLOAD_STATE.lock().unwrap();
-        state.is_downloading = true;
-        state.filename = Some(filename.clone());
-        state.downloaded_size = 0;
-        state.cancel_tx = Some(cancel_tx);
-    }
-
-    // Prepare download
-    let client = Client::new();
-    let response = client.get(&url)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to initiate download: {}", e))?;
-
-    let total_size = response.content_length()
-        .ok_or_else(|| "Failed to get content length".to_string())?;
-
-    // Update total size in global state
-    {
-        let mut state = DOWNLOAD_STATE.lock().unwrap();
-        state.total_size = Some(total_size);
-    }
-
-    // Prepare file for writing
-    fs::create_dir_all(&download_path)
-        .map_err(|e| format!("Failed to create directory: {}", e))?;
-
-    let mut file = File::create(&file_path)
-        .map_err(|e| format!("Failed to create file: {}", e))?;
-
-    // Stream download
-    let mut stream = response.bytes_stream();
-
-    loop {
-        tokio::select! {
-            Some(item) = stream.next() => {
-                let chunk = item
-                    .map_err(|e| format!("Error downloading chunk: {}", e))?;
-                
-                // Write chunk to file
-                file.write_all(&chunk)
-                    .map_err(|e| format!("Failed to write chunk: {}", e))?;
-
-                // Update downloaded size
-                {
-                    let mut state = DOWNLOAD_STATE.lock().unwrap();
-                    state.downloaded_size += chunk.len() as u64;
-                }
-            }
-            _ = cancel_rx.recv() => {
-                // Download cancelled
-                {
-                    let mut state = DOWNLOAD_STATE.lock().unwrap();
-                    state.is_downloading = false;
-                }
-                // Optional: Remove partial download
-                std::fs::remove_file(&file_path).ok();
-                return Err("Download cancelled".to_string());
-            }
-            else => break
-        }
-    }
-
-    // Mark download as complete
-    {
-        let mut state = DOWNLOAD_STATE.lock().unwrap();
-        state.is_downloading = false;
-        state.downloaded_size = total_size;
-    }
-
-    Ok(format!("Model {} downloaded successfully", filename))
-}
-
-#[tauri::command]
-pub fn get_download_progress() -> Result<DownloadProgress, String> {
-    let state = DOWNLOAD_STATE.lock().unwrap();
-    
-    Ok(DownloadProgress {
-        total_size: state.total_size,
-        downloaded_size: state.downloaded_size,
-        is_downloading: state.is_downloading,
-        filename: state.filename.clone(),
-        percentage: state.total_size
-            .map(|total| (state.downloaded_size as f64 / total as f64 * 100.0).round() as u8)
-    })
-}
-
-#[tauri::command]
-pub fn cancel_download() -> Result<(), String> {
-    let mut state = DOWNLOAD_STATE.lock().unwrap();
-    
-    if let Some(cancel_tx) = state.cancel_tx.take() {
-        cancel_tx.try_send(()).map_err(|_| "Failed to send cancellation signal".to_string())?;
-        state.is_downloading = false;
-        Ok(())
-    } else {
-        Err("No active download to cancel".to_string())
-    }
+pub fn install_llama_cpp_command() -> Result<(), AppError> {
+    install_llama_cpp().map_err(|e| AppError::with_cause("Failed to install llama.cpp", e))
 }
-
-#[derive(serde::Serialize)]
-pub struct DownloadProgress {
-    total_size: Option<u64>,
-    downloaded_size: u64,
-    is_downloading: bool,
-    filename: Option<String>,
-    percentage: Option<u8>
-}
-
 #[tauri::command]
-pub fn list_language_models() -> Result<Vec<String>, String> {
+pub fn list_language_models() -> Result<Vec<String>, AppError> {
     let doc_dir = dirs::document_dir()
-        .ok_or_else(|| "Failed to get documents directory".to_string())?;
+        .ok_or_else(|| AppError::internal("Failed to get documents directory"))?;
  
     let model_dir = doc_dir.join("LevChat").join("model");
 
     if !model_dir.exists() {
         fs::create_dir_all(&model_dir)
-            .map_err(|e| format!("Failed to create directory: {}", e))?;
+            .map_err(|e| AppError::with_cause("Failed to create directory", e))?;
         return Ok(vec![]);
     }
 
     let files = fs::read_dir(&model_dir)
-        .map_err(|e| format!("Failed to read directory: {}", e))?
+        .map_err(|e| AppError::with_cause("Failed to read directory", e))?
         .filter_map(|entry| {
             entry.ok()
                 .and_then(|e| {
@@ -590,20 +451,20 @@ pub fn list_language_models() -> Result<Vec<String>, String> {
 }
 
 #[tauri::command]
-pub fn list_embedding_models() -> Result<Vec<String>, String> {
+pub fn list_embedding_models() -> Result<Vec<String>, AppError> {
     let doc_dir = dirs::document_dir()
-        .ok_or_else(|| "Failed to get documents directory".to_string())?;
+        .ok_or_else(|| AppError::internal("Failed to get documents directory"))?;
  
     let embed_model_dir = doc_dir.join("LevChat").join("em_model");
 
     if !embed_model_dir.exists() {
         fs::create_dir_all(&embed_model_dir)
-            .map_err(|e| format!("Failed to create directory: {}", e))?;
+            .map_err(|e| AppError::with_cause("Failed to create directory", e))?;
         return Ok(vec![]);
     }
 
     let files = fs::read_dir(&embed_model_dir)
-        .map_err(|e| format!("Failed to read directory: {}", e))?
+        .map_err(|e| AppError::with_cause("Failed to read directory", e))?
         .filter_map(|entry| {
             entry.ok()
                 .and_then(|e| {
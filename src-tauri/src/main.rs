@@ -3,14 +3,38 @@
 mod lam;
 mod config;
 mod db;
+mod rag;
+mod error;
 
 extern crate serde_json;
 use db::db::*;
+use db::search::search_messages;
+use db::branches::*;
+use db::export::export_chats_command;
+use db::import::import_chats_command;
+use db::trash::*;
+use db::organize::*;
+use db::history::*;
+use db::chat_settings::*;
+use db::titles::{get_auto_title_enabled_command, set_auto_title_enabled_command};
+use db::backup::*;
+use db::analytics::{get_usage_report_command, export_usage_csv_command};
+use db::messages::*;
+use db::fork::fork_chat_command;
+use db::retention::*;
+use db::attachments::*;
+use db::encryption::{
+    get_database_status_command, unlock_database_command,
+    encrypt_database_command, change_database_passphrase_command,
+};
 use lam::llama::*;
 use lam::llamautils::setup_levchat_dirs;
 use lam::settings::check_settings_file;
+use rag::grounding::verify_grounding_command;
+use rag::routing::route_query_command;
 use config::config::configure;
 use config::setup::*;
+use config::downloads::*;
 use anyhow::Result;
 use std::env;
 
@@ -21,7 +45,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let _ = setup_levchat_dirs();
     configure();
     check_settings_file();
-    initialize_database().expect("Failed to initialize database");
+    let db = Db::open().expect("Failed to initialize database");
     init_model_state().await;
 
     let context = tauri::generate_context!();
@@ -31,23 +55,57 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     });
 
     tauri::Builder::default()
+        .manage(db)
         .setup(|app| {
             #[cfg(all(desktop, target_os="macos"))]
             app.set_activation_policy(tauri::ActivationPolicy::Regular);
             
+            start_backup_scheduler(app.handle());
+            start_retention_scheduler(app.handle());
+            if let Err(e) = load_download_settings(&app.state::<Db>()) {
+                log::warn!("Failed to load download settings: {}", e);
+            }
+            if let Err(e) = restore_partial_downloads() {
+                log::warn!("Failed to restore unfinished downloads: {}", e);
+            }
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             download_model,
             list_embedding_models, list_language_models,
-            is_llama_cpp_installed, download_setup,
-            get_download_progress, cancel_download, cancel_setup,
-            get_setup_progress, reset_setup_progress, unzip_setup,
+            is_llama_cpp_installed, download_setup, unzip_setup,
             check_llama_cpp_executable_exists,
             get_selected_em_model, set_em_model, get_selected_model,
             set_model, create_new_chat_command, get_all_chats_command,
             get_chat_messages_command, save_message_command, delete_chat_command,
-            rename_chat_command
+            rename_chat_command, verify_grounding_command, route_query_command,
+            search_messages, edit_message_command, regenerate_message_command,
+            list_message_versions_command, switch_branch_command, export_chats_command,
+            import_chats_command, list_trash_command, restore_chat_command,
+            purge_chat_command, empty_trash_command, get_trash_retention_days_command,
+            set_trash_retention_days_command, list_folders_command, create_folder_command,
+            rename_folder_command, delete_folder_command, move_chat_to_folder_command,
+            set_chat_pinned_command, set_chat_archived_command, set_chat_tags_command,
+            list_tags_command, get_chat_messages_page_command, get_chat_summary_command,
+            get_chat_settings_command, set_chat_settings_command, get_default_chat_settings_command,
+            set_default_chat_settings_command, prepare_chat_prompt_command,
+            get_auto_title_enabled_command, set_auto_title_enabled_command,
+            create_backup_command, validate_backup_command, restore_backup_command,
+            list_backups_command, get_backup_schedule_command, set_backup_schedule_command,
+            get_database_status_command, unlock_database_command,
+            encrypt_database_command, change_database_passphrase_command,
+            get_usage_report_command, export_usage_csv_command,
+            update_message_content_command, get_message_edits_command, delete_message_command,
+            star_message_command, unstar_message_command, list_starred_messages_command,
+            fork_chat_command,
+            get_retention_policy_command, set_retention_policy_command,
+            preview_retention_command, apply_retention_command,
+            add_attachment_command, link_attachments_command, remove_attachment_command,
+            list_chat_attachments_command, get_attachment_text_command, search_chat_attachments_command,
+            start_download_command, get_download_command, list_downloads_command,
+            pause_download_command, resume_download_command, cancel_download_command,
+            clear_finished_downloads_command, get_max_concurrent_downloads_command,
+            set_max_concurrent_downloads_command
         ])
         .run(context)
         .expect("error while running tauri application");
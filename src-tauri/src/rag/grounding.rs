@@ -0,0 +1,273 @@
+use std::collections::HashSet;
+use serde::{Serialize, Deserialize};
+use crate::config::llama_cli;
+use crate::db::db::{get_message, save_message_grounding, Db, MessageGrounding};
+use tauri::State;
+use crate::error::AppError;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetrievedChunk {
+    pub filename: String,
+    pub text: String,
+    // Names of the injection patterns found in this chunk
+    #[serde(default)]
+    pub flags: Vec<String>,
+}
+
+// Without an answer from the judge, a claim counts as supported once this share of its
+// words appears in a source
+const GROUNDING_SUPPORT_THRESHOLD: f32 = 0.5;
+// Claims shorter than this (in words) are too vague to verify
+const MIN_CLAIM_WORDS: usize = 4;
+
+const STOPWORDS: &[&str] = &[
+    "a", "an", "the", "and", "or", "but", "if", "of", "to", "in", "on", "at", "by", "for",
+    "with", "from", "as", "is", "are", "was", "were", "be", "been", "being", "it", "its",
+    "this", "that", "these", "those", "there", "their", "they", "which", "who", "what",
+    "also", "can", "has", "have", "had", "so", "than", "then", "into", "about",
+];
+
+// Words that turn a statement around. Contractions such as "isn't" are split at the
+// apostrophe, so their first half is listed.
+const NEGATIONS: &[&str] = &[
+    "not", "no", "never", "none", "nor", "neither", "nothing", "cannot", "without",
+    "isn", "aren", "wasn", "weren", "doesn", "don", "didn", "won", "hasn", "haven", "hadn",
+    "couldn", "shouldn", "wouldn",
+];
+
+fn content_words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(|word| word.to_lowercase())
+        .filter(|word| word.len() > 1 && !STOPWORDS.contains(&word.as_str()))
+        .collect()
+}
+
+// Splits an answer into sentence-level claims, dropping the [done] marker
+// and fragments too short to be checked.
+pub fn split_claims(answer: &str) -> Vec<String> {
+    let answer = answer.replace("[done]", "");
+    answer
+        .split(['.', '!', '?', '\n'])
+        .map(|claim| claim.trim().trim_start_matches(['-', '*']).trim())
+        .filter(|claim| claim.split_whitespace().count() >= MIN_CLAIM_WORDS)
+        .map(|claim| claim.to_string())
+        .collect()
+}
+
+fn is_negated(words: &HashSet<String>) -> bool {
+    words.iter().any(|word| NEGATIONS.contains(&word.as_str()))
+}
+
+// Fraction of the claim's content words that also appear in the chunk. Shared words say
+// nothing when one side is negated and the other is not, so the sentence of the chunk
+// closest to the claim has to agree with it.
+fn chunk_overlap(claim_words: &HashSet<String>, chunk: &RetrievedChunk) -> f32 {
+    let closest = chunk.text
+        .split(['.', '!', '?', '\n'])
+        .map(content_words)
+        .max_by(|a, b| {
+            lexical_overlap(claim_words, a).partial_cmp(&lexical_overlap(claim_words, b))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+    match closest {
+        Some(sentence) if is_negated(&sentence) == is_negated(claim_words) => {
+            lexical_overlap(claim_words, &content_words(&chunk.text))
+        }
+        _ => 0.0,
+    }
+}
+
+fn lexical_overlap(claim_words: &HashSet<String>, chunk_words: &HashSet<String>) -> f32 {
+    if claim_words.is_empty() {
+        return 0.0;
+    }
+    let shared = claim_words.intersection(chunk_words).count();
+    shared as f32 / claim_words.len() as f32
+}
+
+fn entailment_prompt(claim: &str, chunk: &RetrievedChunk) -> String {
+    format!(
+        r#"Context (from {}):
+{}
+
+Claim: {}
+
+Is the claim fully supported by the context above? Answer with a single word, yes or no.
+Answer: "#,
+        chunk.filename,
+        chunk.text,
+        claim
+    )
+}
+
+pub fn parse_yes_no(answer: &str) -> Option<bool> {
+    let answer = answer.trim().to_lowercase();
+    if answer.starts_with("yes") {
+        Some(true)
+    } else if answer.starts_with("no") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+// Checks each claim of the answer against the chunks that were supplied in the prompt.
+// `judge` runs a prompt through a model and its yes or no decides; when it is missing,
+// fails or gives no clear answer, lexical overlap decides.
+pub fn check_grounding<F>(answer: &str, sources: &[RetrievedChunk], judge: Option<F>) -> MessageGrounding
+where
+    F: Fn(&str) -> Result<String, AppError>,
+{
+    let claims = split_claims(answer);
+    if claims.is_empty() {
+        return MessageGrounding {
+            score: 1.0,
+            unsupported_claims: Vec::new(),
+            checked_at: None,
+        };
+    }
+
+    let mut unsupported_claims = Vec::new();
+
+    for claim in &claims {
+        let claim_words = content_words(claim);
+
+        let best = sources.iter()
+            .enumerate()
+            .map(|(i, chunk)| (i, chunk_overlap(&claim_words, chunk)))
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        let supported = match best {
+            Some((i, overlap)) => {
+                let entailed = judge.as_ref()
+                    .and_then(|judge| judge(&entailment_prompt(claim, &sources[i])).ok())
+                    .and_then(|answer| parse_yes_no(&answer));
+
+                entailed.unwrap_or(overlap >= GROUNDING_SUPPORT_THRESHOLD)
+            }
+            None => false,
+        };
+
+        if !supported {
+            unsupported_claims.push(claim.clone());
+        }
+    }
+
+    let supported_count = claims.len() - unsupported_claims.len();
+
+    MessageGrounding {
+        score: supported_count as f32 / claims.len() as f32,
+        unsupported_claims,
+        checked_at: None,
+    }
+}
+
+// Checks a saved answer against the chunks stored with it when it was generated,
+// and returns the stored result.
+#[tauri::command]
+pub async fn verify_grounding_command(
+    db: State<'_, Db>,
+    message_id: i64,
+    model: Option<String>,
+) -> Result<MessageGrounding, AppError> {
+    let message = get_message(&*db.conn()?, message_id)?;
+    if message.sources.is_empty() {
+        return Err(AppError::invalid_input(format!("Message {} was not generated from retrieved sources", message_id)));
+    }
+
+    let (answer, sources) = (message.content, message.sources);
+    let grounding = tokio::task::spawn_blocking(move || {
+        let judge = model.map(|model| {
+            move |prompt: &str| llama_cli::complete(&model, prompt, 4)
+        });
+        check_grounding(&answer, &sources, judge)
+    })
+    .await
+    .map_err(|e| AppError::with_cause("Grounding check failed", e))?;
+
+    let conn = db.conn()?;
+    save_message_grounding(&conn, message_id, &grounding)?;
+
+    get_message(&conn, message_id)?.grounding
+        .ok_or_else(|| AppError::internal(format!("Grounding of message {} was not saved", message_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Judge = fn(&str) -> Result<String, AppError>;
+
+    fn source(text: &str) -> Vec<RetrievedChunk> {
+        vec![RetrievedChunk { filename: "notes.txt".to_string(), text: text.to_string(), flags: Vec::new() }]
+    }
+
+    fn is_supported(claim: &str, text: &str, judge: Option<Judge>) -> bool {
+        check_grounding(claim, &source(text), judge).unsupported_claims.is_empty()
+    }
+
+    #[test]
+    fn supports_claims_found_in_the_sources() {
+        let text = "The warranty covers the battery for two years. Screens are covered for one year.";
+        assert!(is_supported("The warranty covers the battery for two years", text, None));
+    }
+
+    #[test]
+    fn negation_breaks_the_overlap() {
+        let text = "The warranty covers the battery for two years.";
+        assert!(!is_supported("The warranty does not cover the battery for two years", text, None));
+        assert!(!is_supported("The warranty doesn't cover the battery for two years", text, None));
+
+        let negated = "The warranty does not cover the battery.";
+        assert!(!is_supported("The warranty does cover the battery", negated, None));
+        assert!(is_supported("The warranty does not cover the battery", negated, None));
+    }
+
+    #[test]
+    fn claims_without_overlap_are_unsupported() {
+        let grounding = check_grounding(
+            "Refunds are paid within fourteen days of purchase",
+            &source("The warranty covers the battery for two years."),
+            None::<Judge>,
+        );
+        assert_eq!(grounding.score, 0.0);
+        assert_eq!(grounding.unsupported_claims.len(), 1);
+
+        assert!(!is_supported("Refunds are paid within fourteen days", "", None));
+        assert!(check_grounding("Refunds are paid within fourteen days", &[], None::<Judge>).score == 0.0);
+    }
+
+    #[test]
+    fn judge_decides_when_it_answers() {
+        let text = "The warranty covers the battery for two years.";
+
+        // Full overlap, but the judge says no
+        assert!(!is_supported("The warranty covers the battery for two years", text, Some(|_| Ok("No".to_string()))));
+        // No overlap, but the judge says yes
+        assert!(is_supported("Refunds are paid within fourteen days", text, Some(|_| Ok(" yes.".to_string()))));
+    }
+
+    #[test]
+    fn overlap_decides_when_the_judge_fails_or_is_unclear() {
+        let text = "The warranty covers the battery for two years.";
+        let failing: Judge = |_| Err(AppError::internal("model crashed"));
+        let unclear: Judge = |_| Ok("maybe".to_string());
+
+        for judge in [failing, unclear] {
+            assert!(is_supported("The warranty covers the battery for two years", text, Some(judge)));
+            assert!(!is_supported("Refunds are paid within fourteen days", text, Some(judge)));
+        }
+    }
+
+    #[test]
+    fn scores_the_share_of_supported_claims() {
+        let grounding = check_grounding(
+            "The warranty covers the battery for two years. Refunds are paid within fourteen days.",
+            &source("The warranty covers the battery for two years."),
+            None::<Judge>,
+        );
+        assert_eq!(grounding.score, 0.5);
+        assert_eq!(grounding.unsupported_claims, vec!["Refunds are paid within fourteen days".to_string()]);
+    }
+}
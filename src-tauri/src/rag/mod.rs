@@ -0,0 +1,3 @@
+pub mod grounding;
+pub mod injection;
+pub mod routing;
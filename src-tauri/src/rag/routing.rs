@@ -0,0 +1,189 @@
+use serde::{Serialize, Deserialize};
+use tauri::State;
+use crate::config::llama_cli;
+use crate::db::chat_settings::get_chat_settings;
+use crate::db::db::{Db, MessageRouting};
+use crate::rag::grounding::parse_yes_no;
+use crate::error::AppError;
+
+// Legacy manual opt-in, still honoured as an override
+const RAG_PREFIX: &str = "RAG-";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RetrievalMode {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoutedQuery {
+    pub query: String,
+    pub routing: MessageRouting,
+}
+
+// Removes the "RAG-" prefix, returning the bare query and whether it was present.
+pub fn strip_rag_prefix(query: &str) -> (&str, bool) {
+    match query.trim_start().strip_prefix(RAG_PREFIX) {
+        Some(rest) => (rest.trim_start(), true),
+        None => (query, false),
+    }
+}
+
+fn classifier_prompt(query: &str) -> String {
+    format!(
+        r#"The user has a private collection of PDF documents (reports, papers, manuals).
+Decide whether answering the message below requires looking up those documents,
+or whether it can be answered from general knowledge or the conversation alone.
+
+Message: {}
+
+Does this message need the documents? Answer with a single word, yes or no.
+Answer: "#,
+        query
+    )
+}
+
+// Decides whether a message should go through retrieval. In Auto mode the only router is
+// `classifier`, which runs a prompt through a model. There are no embeddings to compare
+// the query with the document index yet, so no similarity threshold is applied.
+pub fn route_query<F>(query: &str, mode: RetrievalMode, classifier: Option<F>) -> RoutedQuery
+where
+    F: Fn(&str) -> Result<String, AppError>,
+{
+    let (query, has_prefix) = strip_rag_prefix(query);
+    let query = query.to_string();
+
+    let decided = |retrieve: bool, reason: String, overridden: bool| MessageRouting {
+        retrieve,
+        reason,
+        overridden,
+        similarity: None,
+    };
+
+    let routing = match mode {
+        RetrievalMode::Always => decided(true, "Retrieval forced on by user".to_string(), true),
+        RetrievalMode::Never => decided(false, "Retrieval forced off by user".to_string(), true),
+        RetrievalMode::Auto if has_prefix => {
+            decided(true, format!("Message starts with \"{}\"", RAG_PREFIX), true)
+        }
+        RetrievalMode::Auto => {
+            let classified = classifier.as_ref()
+                .map(|classifier| classifier(&classifier_prompt(&query)).map(|answer| parse_yes_no(&answer)));
+
+            match classified {
+                Some(Ok(Some(true))) => decided(
+                    true,
+                    "Classifier says the documents are needed".to_string(),
+                    false,
+                ),
+                Some(Ok(Some(false))) => decided(
+                    false,
+                    "Classifier says the documents are not needed".to_string(),
+                    false,
+                ),
+                Some(Ok(None)) => decided(
+                    false,
+                    "Classifier gave no clear answer".to_string(),
+                    false,
+                ),
+                Some(Err(e)) => decided(
+                    false,
+                    format!("Classifier failed: {}", e),
+                    false,
+                ),
+                None => decided(
+                    false,
+                    "No classifier model available".to_string(),
+                    false,
+                ),
+            }
+        }
+    };
+
+    RoutedQuery { query, routing }
+}
+
+#[tauri::command]
+pub async fn route_query_command(
+    db: State<'_, Db>,
+    query: String,
+    mode: Option<RetrievalMode>,
+    model: Option<String>,
+    chat_id: Option<i64>,
+) -> Result<RoutedQuery, AppError> {
+    // Anything not passed explicitly comes from the chat's settings
+    let (mode, model) = match chat_id {
+        Some(chat_id) => {
+            let settings = get_chat_settings(&*db.conn()?, chat_id)?;
+            (mode.or(Some(settings.rag.mode)), model.or(settings.model))
+        }
+        None => (mode, model),
+    };
+
+    tokio::task::spawn_blocking(move || {
+        let classifier = model.map(|model| {
+            move |prompt: &str| llama_cli::complete(&model, prompt, 4)
+        });
+        route_query(&query, mode.unwrap_or_default(), classifier)
+    })
+    .await
+    .map_err(|e| AppError::with_cause("Retrieval routing failed", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Classifier = fn(&str) -> Result<String, AppError>;
+
+    fn answering(answer: &'static str) -> impl Fn(&str) -> Result<String, AppError> {
+        move |_| Ok(answer.to_string())
+    }
+
+    #[test]
+    fn prefix_forces_retrieval_in_auto_mode() {
+        let routed = route_query("RAG- what does the report say?", RetrievalMode::Auto, Some(answering("no")));
+        assert_eq!(routed.query, "what does the report say?");
+        assert!(routed.routing.retrieve);
+        assert!(routed.routing.overridden);
+    }
+
+    #[test]
+    fn always_and_never_ignore_the_classifier() {
+        let routed = route_query("hello", RetrievalMode::Always, Some(answering("no")));
+        assert!(routed.routing.retrieve && routed.routing.overridden);
+
+        let routed = route_query("RAG- hello", RetrievalMode::Never, Some(answering("yes")));
+        assert_eq!(routed.query, "hello");
+        assert!(!routed.routing.retrieve && routed.routing.overridden);
+    }
+
+    #[test]
+    fn classifier_decides_in_auto_mode() {
+        let routed = route_query("what does the report say?", RetrievalMode::Auto, Some(answering("Yes")));
+        assert!(routed.routing.retrieve);
+        assert!(!routed.routing.overridden);
+
+        let routed = route_query("what is two plus two?", RetrievalMode::Auto, Some(answering(" no.")));
+        assert!(!routed.routing.retrieve);
+        assert_eq!(routed.routing.reason, "Classifier says the documents are not needed");
+    }
+
+    #[test]
+    fn missing_unclear_and_failed_classifiers_have_their_own_reason() {
+        let missing = route_query("hello", RetrievalMode::Auto, None::<Classifier>);
+        let unclear = route_query("hello", RetrievalMode::Auto, Some(answering("perhaps")));
+        let failed = route_query("hello", RetrievalMode::Auto, Some(|_: &str| Err(AppError::internal("model crashed"))));
+
+        for routed in [&missing, &unclear, &failed] {
+            assert!(!routed.routing.retrieve);
+            assert!(routed.routing.similarity.is_none());
+        }
+        assert_eq!(missing.routing.reason, "No classifier model available");
+        assert_eq!(unclear.routing.reason, "Classifier gave no clear answer");
+        assert!(failed.routing.reason.starts_with("Classifier failed"));
+    }
+}